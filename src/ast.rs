@@ -4,8 +4,10 @@ use pest::iterators::Pair;
 
 use std::fs::File;
 use std::io::Read;
+use std::mem;
 use std::rc::Rc;
 
+use error::{Error, Location};
 use parser::*;
 
 #[derive(Debug)]
@@ -23,154 +25,219 @@ pub enum Operation {
     Le,
     Gte,
     Lee,
+    /// Prefix operators, see `Ast::Unary`.
+    Neg,
+    Pos,
+    Not,
 }
 
 #[derive(Debug)]
 pub enum Ast {
     Exp(Operation, Rc<Ast>, Rc<Ast>),
+    Unary(Operation, Rc<Ast>),
+    Int(i64),
     Float(f64),
+    Bool(bool),
     Str(String),
+    Null,
     Var(Vec<Rc<Ast>>),
     Lambda(Vec<String>, Vec<Rc<Ast>>),
-    Call(String, Vec<Rc<Ast>>),
+    Array(Vec<Rc<Ast>>),
+    Dict(Vec<(String, Rc<Ast>)>),
+    /// The callee, a `Var` or the result of another `Call`, and the arguments.
+    Call(Rc<Ast>, Vec<Rc<Ast>>),
     Assign(Rc<Ast>, Rc<Ast>),
+    /// The condition, the block and the else branch, which is `Nothing`, a `Block`
+    /// or the `If` of an `else if`.
     If(Rc<Ast>, Vec<Rc<Ast>>, Rc<Ast>),
+    /// The condition, `Nothing` for `loop {}`, and the block.
     Loop(Rc<Ast>, Vec<Rc<Ast>>),
+    Break,
+    Continue,
+    /// The returned expression, `Null` for a bare `return`.
+    Return(Rc<Ast>),
     Block(Vec<Rc<Ast>>),
     Nothing,
 }
 
-fn consume(pair: Pair<Rule>) -> Rc<Ast> {
+type Res = Result<Rc<Ast>, Error>;
+
+/// Where the reader is, to reject `break`, `continue` and `return` where `compile_str` does.
+struct Reader<'a> {
+    file: &'a str,
+    /// The number of loops around the current statement, inside the current lambda.
+    loops: usize,
+    lambdas: usize,
+}
+
+fn location(r: &Reader, pair: &Pair<Rule>) -> Location {
+    Location::new(r.file, &pair.clone().into_span().start_pos())
+}
+
+fn consume(r: &mut Reader, pair: Pair<Rule>) -> Res {
     match pair.as_rule() {
-        Rule::block => block(pair),
-        Rule::statement => statement(pair),
+        Rule::block => block(r, pair),
+        Rule::statement => statement(r, pair),
         _ => panic!("unexpected token"),
     }
 }
 
-fn block(pair: Pair<Rule>) -> Rc<Ast> {
+fn block(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let mut v = Vec::new();
 
     for pair in pair.into_inner() {
-        v.push(consume(pair));
+        v.push(consume(r, pair)?);
     }
 
-    Rc::new(Ast::Block(v))
+    Ok(Rc::new(Ast::Block(v)))
 }
 
-fn statement(pair: Pair<Rule>) -> Rc<Ast> {
+fn statement(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let next = pair.into_inner().next().unwrap();
 
     match next.as_rule() {
-        Rule::assign => assign(next),
-        Rule::call => call(next),
-        Rule::_if => _if(next),
+        Rule::assign => assign(r, next),
+        Rule::call => call(r, next),
+        Rule::_if => _if(r, next),
+        Rule::_loop => _loop(r, next),
+        Rule::_break if r.loops == 0 => Err(Error::Compile(location(r, &next), String::from("`break` outside of a loop"))),
+        Rule::_break => Ok(Rc::new(Ast::Break)),
+        Rule::_continue if r.loops == 0 => {
+            Err(Error::Compile(location(r, &next), String::from("`continue` outside of a loop")))
+        }
+        Rule::_continue => Ok(Rc::new(Ast::Continue)),
+        Rule::_return => _return(r, next),
         _ => panic!("unrecognized statement: {:?}", next.as_rule()),
     }
 }
 
-fn access(pair: Pair<Rule>) -> Rc<Ast> {
-    let inner = pair.into_inner();
+/// The statements of a block, without the `Block` around them.
+fn statements(r: &mut Reader, pair: Pair<Rule>) -> Result<Vec<Rc<Ast>>, Error> {
+    match Rc::try_unwrap(block(r, pair)?) {
+        Ok(Ast::Block(stmnts)) => Ok(stmnts),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn access(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let mut var = Vec::new();
 
-    for p in inner {
-        print!("{:?} ", p.as_str());
+    for p in pair.into_inner() {
         let x = match p.as_rule() {
             Rule::ident => Rc::new(Ast::Str(String::from(p.as_str()))),
-            Rule::exp => exp(p),
+            Rule::exp => exp(r, p)?,
             _ => panic!("unexpected in access rule"),
         };
         var.push(x);
     }
-    print!("\n");
 
-    Rc::new(Ast::Var(var))
+    Ok(Rc::new(Ast::Var(var)))
 }
 
-fn exp(pair: Pair<Rule>) -> Rc<Ast> {
-    let mut inner = pair.into_inner();
+fn exp(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    climb(r, pair, |r, p| term(r, p), |_, left, op, right| {
+        let the_op = match op.as_rule() {
+            Rule::op_add => Operation::Add,
+            Rule::op_sub => Operation::Sub,
+            Rule::op_mul => Operation::Mul,
+            Rule::op_div => Operation::Div,
+            Rule::op_mod => Operation::Mod,
+            Rule::op_and => Operation::And,
+            Rule::op_or => Operation::Or,
+            Rule::op_eq => Operation::Eq,
+            Rule::op_neq => Operation::Neq,
+            Rule::op_gt => Operation::Gt,
+            Rule::op_le => Operation::Le,
+            Rule::op_gte => Operation::Gte,
+            Rule::op_lee => Operation::Lee,
+            _ => panic!("unknown operation in expression: {:?}", op.as_rule()),
+        };
+
+        Ok(Rc::new(Ast::Exp(the_op, left?, right?)))
+    })
+}
+
+/// The integer literal `s`, which is found at `pair`, see `parser::integer`.
+fn int(r: &Reader, pair: &Pair<Rule>, s: &str) -> Res {
+    integer(s).map(|i| Rc::new(Ast::Int(i))).map_err(|msg| Error::Compile(location(r, pair), msg))
+}
+
+fn term(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    let rule = pair.as_rule();
 
-    let next = inner.next().unwrap();
-    let rule = next.as_rule();
-    let left = match rule {
-        Rule::exp => exp(next),
+    match rule {
+        Rule::exp => exp(r, pair),
         Rule::literal => {
-            let inner = next.into_inner().next().unwrap();
+            let inner = match pair.clone().into_inner().next() {
+                Some(inner) => inner,
+                None => return Ok(Rc::new(Ast::Null)),
+            };
 
             match inner.as_rule() {
                 Rule::numeric => {
-                    let s = inner.as_str().trim();
-
-                    let d: f64 = if let Ok(x) = s.parse() {
-                        x
-                    } else {
-                        s.parse::<i64>().unwrap() as f64
-                    };
+                    let num = inner.into_inner().next().unwrap();
+                    let s = num.as_str();
 
-                    Rc::new(Ast::Float(d))
+                    match num.as_rule() {
+                        Rule::integer => int(r, &pair, s),
+                        _ => Ok(Rc::new(Ast::Float(s.parse().unwrap()))),
+                    }
+                }
+                Rule::boolean => Ok(Rc::new(Ast::Bool(inner.as_str() == "true"))),
+                _ => {
+                    let s = inner.as_str();
+                    let s = unescape(&s[1..s.len() - 1]).map_err(|msg| Error::Compile(location(r, &pair), msg))?;
+                    Ok(Rc::new(Ast::Str(s)))
                 }
-                _ => Rc::new(Ast::Str(String::from(inner.as_str()))),
             }
         }
-        Rule::access => access(next),
+        Rule::unary => unary(r, pair),
+        Rule::call => call(r, pair),
+        Rule::lambda => lambda(r, pair),
+        Rule::array => array(r, pair),
+        Rule::dict => dict(r, pair),
+        Rule::access => access(r, pair),
         _ => panic!("unknown exp: {:?}", rule),
-    };
-
-    if let Some(op) = inner.next() {
-        if let Some(right) = inner.next() {
-
-            let the_op = match op.as_rule() {
-                Rule::op_add => Operation::Add,
-                Rule::op_sub => Operation::Sub,
-                Rule::op_mul => Operation::Mul,
-                Rule::op_div => Operation::Div,
-                Rule::op_mod => Operation::Mod,
-                Rule::op_and => Operation::And,
-                Rule::op_or => Operation::Or,
-                Rule::op_eq => Operation::Eq,
-                Rule::op_neq => Operation::Neq,
-                Rule::op_gt => Operation::Gt,
-                Rule::op_le => Operation::Le,
-                Rule::op_gte => Operation::Gte,
-                Rule::op_lee => Operation::Lee,
-                _ => panic!("unknown operation in expression: {:?}", op.as_rule()),
-            };
+    }
+}
 
-            Rc::new(Ast::Exp(the_op, left, exp(right)))
-        } else {
-            panic!("incomplete expression")
-        }
-    } else {
-        left
+fn unary(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    if let Some(s) = negative_integer(&pair) {
+        return int(r, &pair, &s);
     }
 
+    let mut inner = pair.clone().into_inner();
+    let op = inner.next().unwrap();
+    let operand = inner.next().unwrap();
+
+    let the_op = match op.as_rule() {
+        Rule::op_neg => Operation::Neg,
+        Rule::op_pos => Operation::Pos,
+        Rule::op_not => Operation::Not,
+        _ => panic!("unknown prefix operator: {:?}", op.as_rule()),
+    };
+
+    Ok(Rc::new(Ast::Unary(the_op, term(r, operand)?)))
 }
 
-fn assign(pair: Pair<Rule>) -> Rc<Ast> {
+fn assign(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let mut inner = pair.into_inner();
 
     let v = inner.next().unwrap();
     let ident = match v.as_rule() {
-        Rule::access => access(v),
+        Rule::access => access(r, v)?,
         _ => panic!("expected access"),
     };
 
-    let e = inner.next().unwrap();
-    let ex = match e.as_rule() {
-        Rule::exp => exp(e),
-        Rule::lambda => lambda(e),
-        Rule::dict => dict(e),
-        Rule::array => array(e),
-        _ => panic!("unexpected assign: {:?}", e.as_rule()),
-    };
+    let ex = exp(r, inner.next().unwrap())?;
 
-    Rc::new(Ast::Assign(ident, ex))
+    Ok(Rc::new(Ast::Assign(ident, ex)))
 }
 
-fn lambda(pair: Pair<Rule>) -> Rc<Ast> {
+fn lambda(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let inner = pair.into_inner();
     let mut names = Vec::new();
-    let mut statements = Vec::new();
+    let mut body = Vec::new();
 
     for node in inner {
         match node.as_rule() {
@@ -182,81 +249,130 @@ fn lambda(pair: Pair<Rule>) -> Rc<Ast> {
                 }
             }
             Rule::block => {
-                if let Ok(Ast::Block(stmnts)) = Rc::try_unwrap(block(node)) {
-                    statements = stmnts;
-                }
+                // loops around the lambda can not be left from inside of it
+                let loops = mem::replace(&mut r.loops, 0);
+                r.lambdas += 1;
+                let res = statements(r, node);
+                r.lambdas -= 1;
+                r.loops = loops;
+
+                body = res?;
             }
             _ => panic!("unexpected element"),
         }
     }
 
-    Rc::new(Ast::Lambda(names, statements))
+    Ok(Rc::new(Ast::Lambda(names, body)))
 }
 
-fn call(pair: Pair<Rule>) -> Rc<Ast> {
+fn call(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let mut call = pair.into_inner();
+    let mut callee = access(r, call.next().unwrap())?;
 
-    let name = String::from(call.next().unwrap().as_str());
-
-    let mut params = Vec::new();
+    // every further argument list calls the result of the one before
+    for args in call {
+        let mut params = Vec::new();
 
-    if let Some(ps) = call.next() {
-        for mut param in ps.into_inner() {
-            match param.as_rule() {
-                Rule::exp => params.push(exp(param)),
-                _ => panic!("unexpected stuff"),
+        if let Some(ps) = args.into_inner().next() {
+            for param in ps.into_inner() {
+                match param.as_rule() {
+                    Rule::exp => params.push(exp(r, param)?),
+                    _ => panic!("unexpected stuff"),
+                }
             }
         }
+
+        callee = Rc::new(Ast::Call(callee, params));
     }
 
-    Rc::new(Ast::Call(name, params))
+    Ok(callee)
 }
 
-fn _if(pair: Pair<Rule>) -> Rc<Ast> {
+/// Also used for `_elseif`, which has the same structure.
+fn _if(r: &mut Reader, pair: Pair<Rule>) -> Res {
     let mut the_if = pair.into_inner();
 
-    let cond = exp(the_if.next().unwrap());
-    let block = if let Ok(Ast::Block(b)) = Rc::try_unwrap(block(the_if.next().unwrap())) {
-        b
-    } else {
-        Vec::new()
-    };
+    let cond = exp(r, the_if.next().unwrap())?;
+    let block = statements(r, the_if.next().unwrap())?;
     let elsy = match the_if.next() {
-        Some(block) => _else(block),
+        Some(next) => match next.as_rule() {
+            Rule::_elseif => _if(r, next)?,
+            _ => Rc::new(Ast::Block(statements(r, next.into_inner().next().unwrap())?)),
+        },
         None => Rc::new(Ast::Nothing),
     };
 
-    Rc::new(Ast::If(cond, block, elsy))
+    Ok(Rc::new(Ast::If(cond, block, elsy)))
 }
 
-fn _else(pair: Pair<Rule>) -> Rc<Ast> {
-    let mut the_else = pair.into_inner();
+fn _loop(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    let mut inner = pair.into_inner().peekable();
+
+    let cond = match inner.peek().map(|p| p.as_rule()) {
+        Some(Rule::exp) => exp(r, inner.next().unwrap())?,
+        _ => Rc::new(Ast::Nothing),
+    };
+
+    r.loops += 1;
+    let res = statements(r, inner.next().unwrap());
+    r.loops -= 1;
 
-    Rc::new(Ast::Nothing)
+    Ok(Rc::new(Ast::Loop(cond, res?)))
 }
 
-fn dict(pair: Pair<Rule>) -> Rc<Ast> {
-    let mut the_else = pair.into_inner();
+fn _return(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    if r.lambdas == 0 {
+        return Err(Error::Compile(location(r, &pair), String::from("`return` outside of a lambda")));
+    }
 
-    Rc::new(Ast::Nothing)
+    let val = match pair.into_inner().next() {
+        Some(val) => exp(r, val)?,
+        None => Rc::new(Ast::Null),
+    };
+
+    Ok(Rc::new(Ast::Return(val)))
+}
+
+fn dict(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    let mut entries = Vec::new();
+
+    for el in pair.into_inner() {
+        let mut el = el.into_inner();
+        let key = String::from(el.next().unwrap().as_str());
+
+        entries.push((key, exp(r, el.next().unwrap())?));
+    }
+
+    Ok(Rc::new(Ast::Dict(entries)))
 }
 
-fn array(pair: Pair<Rule>) -> Rc<Ast> {
-    let mut the_else = pair.into_inner();
+fn array(r: &mut Reader, pair: Pair<Rule>) -> Res {
+    let items: Result<Vec<_>, Error> = pair.into_inner().map(|p| exp(r, p)).collect();
 
-    Rc::new(Ast::Nothing)
+    Ok(Rc::new(Ast::Array(items?)))
 }
 
-pub fn read_file(filename: &str) -> Rc<Ast> {
+pub fn read_file(filename: &str) -> Result<Rc<Ast>, Error> {
     let mut source = String::new();
-    let mut file = File::open(filename).unwrap();
+    let mut file = File::open(filename)?;
+
+    file.read_to_string(&mut source)?;
 
-    file.read_to_string(&mut source).unwrap();
+    read_str(filename, &source)
+}
 
-    let pair = RunjitParser::parse(Rule::input, &source)
-        .unwrap_or_else(|e| panic!("{}", e))
+/// Build the AST of a script from memory, `name` is used as file name in errors.
+pub fn read_str(name: &str, source: &str) -> Result<Rc<Ast>, Error> {
+    let pair = RunjitParser::parse(Rule::input, source)
+        .map_err(|e| Error::syntax(name, e))?
         .next()
         .unwrap();
 
-    consume(pair)
+    let mut reader = Reader {
+        file: name,
+        loops: 0,
+        lambdas: 0,
+    };
+
+    consume(&mut reader, pair)
 }
@@ -8,11 +8,8 @@ use clap::{Arg, App};
 use std::io::Write;
 use std::sync::Mutex;
 
-use runjit::jit::Context;
-
-unsafe fn myprint(val: *const runjit::jit::Value) {
-    println!("in runjit {:?}", *val);
-}
+use runjit::jit::{Context, Value};
+use runjit::Error;
 
 struct FileLogger {
     out: Option<Mutex<std::fs::File>>,
@@ -34,8 +31,6 @@ impl log::Log for FileLogger {
     fn flush(&self) {}
 }
 
-static mut LOGGER: FileLogger = FileLogger { out: None };
-
 fn main() {
     let matches = App::new("runjit - cli")
         .version("0.1")
@@ -44,25 +39,34 @@ fn main() {
 
     let filename = matches.value_of("file").unwrap();
 
-    unsafe {
-        LOGGER.out = Some(Mutex::new(std::fs::File::create("cli.log").unwrap()));
-        log::set_logger(&LOGGER).unwrap();
-    }
+    let logger = FileLogger {
+        out: Some(Mutex::new(std::fs::File::create("cli.log").unwrap())),
+    };
+    log::set_logger(Box::leak(Box::new(logger))).unwrap();
     log::set_max_level(log::LevelFilter::Debug);
 
     debug!("start");
 
     let mut ctx = Context::new();
 
-    ctx.add_fn("print", myprint as *mut _, 1);
+    ctx.register("print", |val: &Value| -> Result<(), Error> {
+        println!("in runjit {:?}", val);
+        Ok(())
+    });
 
     debug!("--- read ---");
 
-    ctx.read_file(filename);
+    if let Err(e) = ctx.read_file(filename) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     debug!("--- run ---");
 
-    ctx.run();
+    if let Err(e) = ctx.run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     debug!("{:?}", ctx.get("myvar"));
     debug!("{:?}", ctx.get("x"));
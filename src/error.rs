@@ -0,0 +1,135 @@
+//!
+//! Errors reported while compiling a script, or calling into it.
+//!
+use pest;
+use pest::Position;
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use parser::Rule;
+
+/// Where in a script something went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    /// The complete source line.
+    pub snippet: String,
+}
+
+impl Location {
+    pub(crate) fn new(file: &str, pos: &Position) -> Location {
+        let (line, column) = pos.line_col();
+
+        Location {
+            file: file.to_string(),
+            line,
+            column,
+            snippet: pos.line_of().trim_end_matches(&['\r', '\n'][..]).to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The script could not be read.
+    Io(io::Error),
+    /// The script does not match the grammar.
+    Syntax(Location, String),
+    /// Valid syntax which can not be compiled, like `break` outside of a loop.
+    Compile(Location, String),
+    /// LLVM rejected the generated module.
+    Verify(String),
+    /// A lambda could not be called from Rust, see `Context::call`,
+    /// or a host function was called with the wrong number of arguments.
+    Call(String),
+    /// A value does not have the type a conversion expects.
+    Type(String),
+    /// The script failed while running, like an index out of range.
+    Runtime(String),
+}
+
+impl Error {
+    pub(crate) fn syntax(file: &str, err: pest::Error<Rule>) -> Error {
+        match err {
+            pest::Error::ParsingError { positives, negatives, pos } => {
+                let mut message = Vec::new();
+
+                if !positives.is_empty() {
+                    message.push(format!("expected {}", rule_list(&positives)));
+                }
+
+                if !negatives.is_empty() {
+                    message.push(format!("unexpected {}", rule_list(&negatives)));
+                }
+
+                if message.is_empty() {
+                    message.push(String::from("unknown parsing error"));
+                }
+
+                Error::Syntax(Location::new(file, &pos), message.join(", "))
+            }
+            pest::Error::CustomErrorPos { message, pos } => Error::Syntax(Location::new(file, &pos), message),
+            pest::Error::CustomErrorSpan { message, span } => {
+                Error::Syntax(Location::new(file, &span.start_pos()), message)
+            }
+        }
+    }
+
+    /// The location in the script, if the error has one.
+    pub fn location(&self) -> Option<&Location> {
+        match *self {
+            Error::Syntax(ref loc, _) | Error::Compile(ref loc, _) => Some(loc),
+            Error::Io(_) | Error::Verify(_) | Error::Call(_) | Error::Type(_) | Error::Runtime(_) => None,
+        }
+    }
+}
+
+fn rule_list(rules: &[Rule]) -> String {
+    let names: Vec<String> = rules.iter().map(|r| format!("{:?}", r)).collect();
+    names.join(", ")
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e)?,
+            Error::Syntax(ref loc, ref msg) => write!(f, "{}: syntax error: {}", loc, msg)?,
+            Error::Compile(ref loc, ref msg) => write!(f, "{}: {}", loc, msg)?,
+            Error::Verify(ref msg) => write!(f, "invalid module: {}", msg)?,
+            Error::Call(ref msg) => write!(f, "call failed: {}", msg)?,
+            Error::Type(ref msg) => write!(f, "type error: {}", msg)?,
+            Error::Runtime(ref msg) => write!(f, "runtime error: {}", msg)?,
+        }
+
+        if let Some(loc) = self.location() {
+            write!(f, "\n  | {}\n  | {:>2$}", loc.snippet, "^", loc.column)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
@@ -4,7 +4,6 @@
 use std::collections::BTreeMap;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::ops::Deref;
 
 use ast::*;
 use types::*;
@@ -50,15 +49,7 @@ impl Context {
         }
     }
 
-    pub fn get_by_ast(&self, var: &Vec<Rc<Ast>>) -> Option<Rc<Value>> {
-        for i in var {
-            match **i {
-                Ast::Str(ref name) => {}
-                Ast::Exp(_, _, _) => {}
-                _ => {}
-            }
-        }
-
+    pub fn get_by_ast(&self, _var: &[Rc<Ast>]) -> Option<Rc<Value>> {
         None
     }
 
@@ -67,97 +58,140 @@ impl Context {
         vals.insert(name.to_string(), val);
     }
 
-    pub fn set_by_ast(&self, var: &Vec<Rc<Ast>>, val: Rc<Value>) {}
+    pub fn set_by_ast(&self, _var: &[Rc<Ast>], _val: Rc<Value>) {}
 }
 
 
 pub fn run(ctx: Rc<Context>, ast: Rc<Ast>) -> Result<Rc<Value>, String> {
-    match ast.deref() {
-        &Ast::Block(ref b) => block(ctx.clone(), b),
+    match *ast {
+        Ast::Block(ref b) => block(ctx.clone(), b),
         _ => Err(String::from("unexpected ast element")),
     }
 }
 
-fn block(ctx: Rc<Context>, data: &Vec<Rc<Ast>>) -> Result<Rc<Value>, String> {
+fn block(ctx: Rc<Context>, data: &[Rc<Ast>]) -> Result<Rc<Value>, String> {
     for stmnt in data {
-        let res = match stmnt.deref() {
-            &Ast::Assign(ref name, ref ast) => assign(ctx.clone(), name.clone(), ast.clone()),
-            &Ast::Call(ref name, ref ast) => call(ctx.clone(), name, ast),
-            &Ast::If(ref exp, ref block, ref _else) => {
-                _if(ctx.clone(), exp.clone(), &block, _else.clone())
+        let res = match **stmnt {
+            Ast::Assign(ref name, ref ast) => assign(ctx.clone(), name.clone(), ast.clone()),
+            Ast::Call(ref callee, ref ast) => call(ctx.clone(), callee, ast),
+            Ast::If(ref exp, ref block, ref _else) => {
+                _if(ctx.clone(), exp.clone(), block, _else.clone())
             }
             _ => Err(String::from("unexpected ast element")),
         };
 
-        if res.is_err() {
-            return res;
-        }
+        res?;
     }
 
     Ok(Rc::new(Value::Null))
 }
 
 fn exp(ctx: Rc<Context>, ast: Rc<Ast>) -> Result<Rc<Value>, String> {
-    match ast.deref() {
-        &Ast::Str(ref data) => Ok(Rc::new(Value::String(data.clone()))),
-        &Ast::Float(ref data) => Ok(Rc::new(Value::Float(*data))),
-        &Ast::Lambda(ref params, ref stmnts) => {
+    match *ast {
+        Ast::Str(ref data) => Ok(Rc::new(Value::String(data.clone()))),
+        Ast::Int(data) => Ok(Rc::new(Value::Int(data))),
+        Ast::Float(data) => Ok(Rc::new(Value::Float(data))),
+        Ast::Bool(data) => Ok(Rc::new(Value::Bool(data))),
+        Ast::Null => Ok(Rc::new(Value::Null)),
+        Ast::Lambda(ref params, ref stmnts) => {
             Ok(Rc::new(Value::Lambda(params.clone(), stmnts.clone())))
         }
-        &Ast::Var(ref tokens) => {
+        Ast::Call(ref callee, ref args) => call(ctx, callee, args),
+        Ast::Unary(ref op, ref operand) => unary(op, &*exp(ctx, operand.clone())?),
+        Ast::Var(ref tokens) => {
             ctx.get_by_ast(tokens).ok_or(
                 String::from("unknown variable"),
             )
         }
-        &Ast::Exp(ref op, ref left, ref right) => {
-            let l = exp(ctx.clone(), left.clone());
-            let r = exp(ctx.clone(), right.clone());
-
-            match l {
-                Ok(v) => {
-                    if let Value::Float(lf) = *v {
-                        match r {
-                            Ok(v) => {
-                                if let Value::Float(rf) = *v {
-                                    match op {
-                                        &Operation::Add => Ok(Rc::new(Value::Float(lf + rf))),
-                                        &Operation::Sub => Ok(Rc::new(Value::Float(lf - rf))),
-                                        &Operation::Mul => Ok(Rc::new(Value::Float(lf * rf))),
-                                        &Operation::Div => Ok(Rc::new(Value::Float(lf / rf))),
-                                        &Operation::Mod => Ok(Rc::new(Value::Float(lf % rf))),
-                                        &Operation::Eq => {
-                                            if lf == rf {
-                                                Ok(Rc::new(Value::Float(1.0)))
-                                            } else {
-                                                Ok(Rc::new(Value::Null))
-                                            }
-                                        }
-                                        &Operation::Neq => {
-                                            if lf != rf {
-                                                Ok(Rc::new(Value::Float(1.0)))
-                                            } else {
-                                                Ok(Rc::new(Value::Null))
-                                            }
-                                        }
-                                        _ => Err(String::from("unsupported operation")),
-                                    }
-                                } else {
-                                    Err(String::from("only can calculate numbers"))
-                                }
-                            }
-                            Err(e) => Err(e),
-                        }
-                    } else {
-                        Err(String::from("only can calculate numbers"))
-                    }
-                }
-                Err(e) => Err(e),
+        Ast::Exp(ref op, ref left, ref right) => {
+            if let Operation::And | Operation::Or = *op {
+                return logic(ctx, op, left.clone(), right.clone());
+            }
+
+            let l = exp(ctx.clone(), left.clone())?;
+            let r = exp(ctx, right.clone())?;
+
+            match (&*l, &*r) {
+                (Value::Int(lf), Value::Int(rf)) => int_op(op, *lf, *rf),
+                (Value::Int(lf), Value::Float(rf)) => float_op(op, *lf as f64, *rf),
+                (Value::Float(lf), Value::Int(rf)) => float_op(op, *lf, *rf as f64),
+                (Value::Float(lf), Value::Float(rf)) => float_op(op, *lf, *rf),
+                _ => Err(String::from("only can calculate numbers")),
             }
         }
-        _ => Err(format!("unexpected expression")),
+        _ => Err(String::from("unexpected expression")),
+    }
+}
+
+/// Integers stay integers, `/` truncates. Overflow and division by zero are errors.
+fn int_op(op: &Operation, l: i64, r: i64) -> Result<Rc<Value>, String> {
+    let res = match *op {
+        Operation::Add => l.checked_add(r),
+        Operation::Sub => l.checked_sub(r),
+        Operation::Mul => l.checked_mul(r),
+        Operation::Div => l.checked_div(r),
+        Operation::Mod => l.checked_rem(r),
+        Operation::Eq => return Ok(Rc::new(Value::Bool(l == r))),
+        Operation::Neq => return Ok(Rc::new(Value::Bool(l != r))),
+        Operation::Gt => return Ok(Rc::new(Value::Bool(l > r))),
+        Operation::Le => return Ok(Rc::new(Value::Bool(l < r))),
+        Operation::Gte => return Ok(Rc::new(Value::Bool(l >= r))),
+        Operation::Lee => return Ok(Rc::new(Value::Bool(l <= r))),
+        _ => return Err(String::from("unsupported operation")),
+    };
+
+    match res {
+        Some(i) => Ok(Rc::new(Value::Int(i))),
+        None if r == 0 => Err(String::from("division by zero")),
+        None => Err(String::from("integer overflow")),
+    }
+}
+
+/// Mixed operands are promoted to floats.
+fn float_op(op: &Operation, l: f64, r: f64) -> Result<Rc<Value>, String> {
+    match *op {
+        Operation::Add => Ok(Rc::new(Value::Float(l + r))),
+        Operation::Sub => Ok(Rc::new(Value::Float(l - r))),
+        Operation::Mul => Ok(Rc::new(Value::Float(l * r))),
+        Operation::Div => Ok(Rc::new(Value::Float(l / r))),
+        Operation::Mod => Ok(Rc::new(Value::Float(l % r))),
+        Operation::Eq => Ok(Rc::new(Value::Bool(l == r))),
+        Operation::Neq => Ok(Rc::new(Value::Bool(l != r))),
+        Operation::Gt => Ok(Rc::new(Value::Bool(l > r))),
+        Operation::Le => Ok(Rc::new(Value::Bool(l < r))),
+        Operation::Gte => Ok(Rc::new(Value::Bool(l >= r))),
+        Operation::Lee => Ok(Rc::new(Value::Bool(l <= r))),
+        _ => Err(String::from("unsupported operation")),
+    }
+}
+
+/// Prefix operators, `-` and `+` only work on numbers, `!` on the truthiness of any value.
+fn unary(op: &Operation, val: &Value) -> Result<Rc<Value>, String> {
+    match (op, val) {
+        (Operation::Not, _) => Ok(Rc::new(Value::Bool(!val.is_truthy()))),
+        (Operation::Neg, Value::Int(i)) => i.checked_neg()
+            .map(|i| Rc::new(Value::Int(i)))
+            .ok_or_else(|| String::from("integer overflow")),
+        (Operation::Neg, Value::Float(f)) => Ok(Rc::new(Value::Float(-f))),
+        (Operation::Pos, Value::Int(i)) => Ok(Rc::new(Value::Int(*i))),
+        (Operation::Pos, Value::Float(f)) => Ok(Rc::new(Value::Float(*f))),
+        _ => Err(String::from("only can negate numbers")),
     }
 }
 
+/// `&&` and `||` work on the truthiness of any value, see `Value::is_truthy`.
+/// The right side is only evaluated if the left one does not decide the result.
+fn logic(ctx: Rc<Context>, op: &Operation, left: Rc<Ast>, right: Rc<Ast>) -> Result<Rc<Value>, String> {
+    let l = exp(ctx.clone(), left)?.is_truthy();
+
+    let res = match *op {
+        Operation::And => l && exp(ctx, right)?.is_truthy(),
+        _ => l || exp(ctx, right)?.is_truthy(),
+    };
+
+    Ok(Rc::new(Value::Bool(res)))
+}
+
 fn assign(ctx: Rc<Context>, name: Rc<Ast>, ast: Rc<Ast>) -> Result<Rc<Value>, String> {
     if let Ok(val) = exp(ctx.clone(), ast) {
         if let Ast::Var(ref tokens) = *name {
@@ -168,8 +202,15 @@ fn assign(ctx: Rc<Context>, name: Rc<Ast>, ast: Rc<Ast>) -> Result<Rc<Value>, St
     Ok(Rc::new(Value::Null))
 }
 
-fn call(ctx: Rc<Context>, name: &str, ast: &Vec<Rc<Ast>>) -> Result<Rc<Value>, String> {
-    let maybe = ctx.get(name);
+fn call(ctx: Rc<Context>, callee: &Rc<Ast>, ast: &[Rc<Ast>]) -> Result<Rc<Value>, String> {
+    // a plain name is looked up, anything else, like the result of a call, is evaluated
+    let maybe = match **callee {
+        Ast::Var(ref tokens) => match tokens.first().map(|t| &**t) {
+            Some(Ast::Str(name)) if tokens.len() == 1 => ctx.get(name),
+            _ => ctx.get_by_ast(tokens),
+        },
+        _ => Some(exp(ctx.clone(), callee.clone())?),
+    };
 
     if let Some(val) = maybe {
         let params: Vec<Rc<Value>> = ast.iter()
@@ -178,10 +219,14 @@ fn call(ctx: Rc<Context>, name: &str, ast: &Vec<Rc<Ast>>) -> Result<Rc<Value>, S
 
         match *val {
             Value::Lambda(ref names, ref stmnts) => {
+                if names.len() != params.len() {
+                    return Err(format!("lambda takes {} arguments, {} given", names.len(), params.len()));
+                }
+
                 let new_ctx = Context::with_parent(ctx);
 
-                for i in 0..params.len() {
-                    new_ctx.set(&names[i], params[i].clone());
+                for (name, param) in names.iter().zip(params) {
+                    new_ctx.set(name, param);
                 }
 
                 block(new_ctx, stmnts)
@@ -197,22 +242,18 @@ fn call(ctx: Rc<Context>, name: &str, ast: &Vec<Rc<Ast>>) -> Result<Rc<Value>, S
 fn _if(
     ctx: Rc<Context>,
     ex: Rc<Ast>,
-    blck: &Vec<Rc<Ast>>,
+    blck: &[Rc<Ast>],
     el: Rc<Ast>,
 ) -> Result<Rc<Value>, String> {
-    let res = exp(ctx.clone(), ex);
+    let res = exp(ctx.clone(), ex)?;
 
-    match res {
-        Ok(r) => {
-            match *r {
-                Value::Null => Ok(Rc::new(Value::Null)),
-                _ => {
-                    let new_ctx = Context::with_parent(ctx);
+    if res.is_truthy() {
+        return block(Context::with_parent(ctx), blck);
+    }
 
-                    block(new_ctx, blck)
-                }
-            }
-        }
-        Err(e) => Err(e),
+    match *el {
+        Ast::Block(ref b) => block(Context::with_parent(ctx), b),
+        Ast::If(ref exp, ref b, ref el) => _if(ctx, exp.clone(), b, el.clone()),
+        _ => Ok(Rc::new(Value::Null)),
     }
 }
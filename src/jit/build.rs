@@ -5,6 +5,10 @@
 //! TODO: optemize pure expressions
 //! TODO: create constants at build as values, instead of on execution time
 //!
+//! Every expression results in a value owned by the generated code, which is given back with
+//! `__value_delete` as soon as it is used up. Locals own the value stored in their slot until
+//! they are reassigned or the lambda returns. See `jit::callbacks` for the callback side.
+//!
 use pest::iterators::Pair;
 
 use llvm;
@@ -13,12 +17,15 @@ use llvm::core::*;
 
 use std::collections::BTreeMap;
 use std::ffi::CString;
+use std::mem;
+use std::ptr;
 
 use parser::*;
 
+use error::{Error, Location};
 use jit::Context;
 
-pub fn consume(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+pub fn consume(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "consume");
 
     match pair.as_rule() {
@@ -28,38 +35,56 @@ pub fn consume(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
     }
 }
 
-fn block(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+fn block(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "block");
     let mut last = 0 as LLVMValueRef;
 
     for pair in pair.into_inner() {
-        last = consume(ctx, pair);
+        last = consume(ctx, pair)?;
     }
 
-    last
+    Ok(last)
 }
 
-fn statement(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+fn statement(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "statement");
     let next = pair.into_inner().next().unwrap();
 
     match next.as_rule() {
         Rule::assign => assign(ctx, next),
-        Rule::call => call(ctx, next),
-        //     // Rule::_if => _if(next),
+        Rule::call => {
+            let res = call(ctx, next)?;
+            unsafe { build_delete(ctx, res) };
+            Ok(res)
+        }
+        Rule::_if => _if(ctx, next),
+        Rule::_loop => _loop(ctx, next),
+        Rule::_break => _break(ctx, next),
+        Rule::_continue => _continue(ctx, next),
+        Rule::_return => _return(ctx, next),
         _ => panic!("unrecognized statement: {:?}", next.as_rule()),
     }
 }
 
+/// A new `Str`, the bytes are kept in a constant global without a terminating NUL,
+/// so `s` may contain NUL bytes.
 unsafe fn build_string(ctx: &mut Context, s: &str) -> LLVMValueRef {
     debug!(target: "runjit.build", "build_string");
-    let cstr = LLVMBuildGlobalStringPtr(
+    let i8_t = LLVMInt8TypeInContext(ctx.llvm_ctx);
+    let data = LLVMConstStringInContext(ctx.llvm_ctx, s.as_ptr() as *const _, s.len() as u32, 1);
+    let global = LLVMAddGlobal(ctx.llvm_module, LLVMTypeOf(data), b"__str\0".as_ptr() as *const _);
+
+    LLVMSetInitializer(global, data);
+    LLVMSetGlobalConstant(global, 1);
+    LLVMSetLinkage(global, llvm::LLVMLinkage::LLVMPrivateLinkage);
+
+    let bytes = LLVMBuildBitCast(
         ctx.llvm_builder,
-        CString::new(s).unwrap().as_ptr(),
-        b"__str\0".as_ptr() as *const _,
+        global,
+        LLVMPointerType(i8_t, 0),
+        b"__str_bytes\0".as_ptr() as *const _,
     );
-
-    let args = vec![cstr];
+    let args = [bytes, LLVMConstInt(LLVMInt64TypeInContext(ctx.llvm_ctx), s.len() as u64, 0)];
     let string_from = ctx.extern_functions.get("__string_from").unwrap();
     LLVMBuildCall(
         ctx.llvm_builder,
@@ -70,38 +95,101 @@ unsafe fn build_string(ctx: &mut Context, s: &str) -> LLVMValueRef {
     )
 }
 
+fn location(ctx: &Context, pair: &Pair<Rule>) -> Location {
+    Location::new(&ctx.file, &pair.clone().into_span().start_pos())
+}
+
 fn string(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
     debug!(target: "runjit.build", "string");
     let s = pair.as_str();
     unsafe { build_string(ctx, s) }
 }
 
+/// Where the variable an access starts with lives.
+enum Scope {
+    /// A param, local or captured variable of the current lambda, with its slot.
+    Local(LLVMValueRef),
+    /// A global variable, looked up when the code runs. Assigning to a name which is not
+    /// a local yet inside of a lambda creates a local instead.
+    Global,
+}
+
+/// Find `name` in the locals of the current lambda first, then in the enclosing lambdas,
+/// and fall back to a global variable.
+///
+/// A variable of an enclosing lambda is captured, it becomes a local of the current lambda
+/// which is loaded from the environment of the closure on entry, see `lambda`.
+unsafe fn resolve(ctx: &mut Context, name: &str) -> Scope {
+    let depth = ctx.local_stack.len();
+
+    if depth == 0 {
+        return Scope::Global;
+    }
+
+    if let Some(slot) = ctx.local_stack[depth - 1].get(name) {
+        return Scope::Local(*slot);
+    }
+
+    if !ctx.local_stack[..depth - 1].iter().any(|locals| locals.contains_key(name)) {
+        return Scope::Global;
+    }
+
+    debug!(target: "runjit.build", "capture {}", name);
+    let slot = build_local(ctx, name);
+    let index = ctx.capture_stack[depth - 1].len();
+
+    // the entry block only sets up the locals and ends with a jump to the body
+    let func = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctx.llvm_builder));
+    let entry = LLVMGetEntryBasicBlock(func);
+    let builder = LLVMCreateBuilderInContext(ctx.llvm_ctx);
+    LLVMPositionBuilderBefore(builder, LLVMGetBasicBlockTerminator(entry));
+
+    let env_get = ctx.extern_functions.get("__env_get").unwrap();
+    let args = [LLVMGetParam(func, 0), LLVMConstInt(LLVMInt64TypeInContext(ctx.llvm_ctx), index as u64, 0)];
+    let val = LLVMBuildCall(
+        builder,
+        env_get.0,
+        args.as_ptr() as *mut _,
+        args.len() as u32,
+        b"__captured\0".as_ptr() as *const _,
+    );
+    LLVMBuildStore(builder, val, slot);
+    LLVMDisposeBuilder(builder);
+
+    ctx.local_stack[depth - 1].insert(name.to_string(), slot);
+    ctx.capture_stack[depth - 1].push(name.to_string());
+
+    Scope::Local(slot)
+}
+
 enum AccessToken {
-    Pure(String),
-    Parts(Vec<LLVMValueRef>),
+    Pure(String, Scope),
+    /// The parts of the path, see `jit::path`, and where its first part lives.
+    Parts(Vec<LLVMValueRef>, Scope),
 }
 
-fn access(ctx: &mut Context, pair: Pair<Rule>) -> AccessToken {
+fn access(ctx: &mut Context, pair: Pair<Rule>) -> Result<AccessToken, Error> {
     debug!(target: "runjit.build", "access");
     let inner: Vec<_> = pair.into_inner().collect();
+    let name = inner[0].as_str().trim().to_string();
+    let scope = unsafe { resolve(ctx, &name) };
 
     if inner.len() == 1 {
-        let name = inner[0].as_str().trim().to_string();
-        AccessToken::Pure(name)
+        Ok(AccessToken::Pure(name, scope))
     } else {
         let mut parts = Vec::new();
 
         for p in inner {
             let x = match p.as_rule() {
                 Rule::ident => string(ctx, p),
-                Rule::exp => unsafe { exp(ctx, p) },
+                Rule::exp => unsafe { exp(ctx, p)? },
                 _ => panic!("unexpected in access rule"),
             };
 
             parts.push(x);
         }
 
-        AccessToken::Parts(parts)
+        Ok(AccessToken::Parts(parts, scope))
     }
 }
 
@@ -113,16 +201,16 @@ fn build_access_array(ctx: &mut Context, parts: &Vec<LLVMValueRef>) -> LLVMValue
         LLVMBuildCall(
             ctx.llvm_builder,
             an.0,
-            0 as *mut LLVMValueRef,
+            ptr::null_mut(),
             0,
             b"__array_new\0".as_ptr() as *const _,
         )
     };
 
-    let array_push = ctx.extern_functions.get("__array_push").unwrap().clone();
+    let array_push = ctx.extern_functions["__array_push"];
 
     for p in parts {
-        let args = vec![an_ref, *p];
+        let args = [an_ref, *p];
         unsafe {
             LLVMBuildCall(
                 ctx.llvm_builder,
@@ -130,47 +218,76 @@ fn build_access_array(ctx: &mut Context, parts: &Vec<LLVMValueRef>) -> LLVMValue
                 args.as_ptr() as *mut LLVMValueRef,
                 args.len() as u32,
                 b"__array_push\0".as_ptr() as *const _,
-            )
+            );
+            build_delete(ctx, *p);
         };
     }
     debug!(target: "runjit.build", "build_access_array <<");
     an_ref
 }
 
+/// Allocate a stack slot for a local variable in the entry block of the current function,
+/// so the variable survives branches and can be reassigned from any block.
+unsafe fn build_local(ctx: &mut Context, name: &str) -> LLVMValueRef {
+    debug!(target: "runjit.build", "build_local {}", name);
+    let slot = build_entry_alloca(ctx, ctx.llvm_ptr, name);
+    let builder = LLVMCreateBuilderInContext(ctx.llvm_ctx);
+
+    LLVMPositionBuilder(builder, LLVMGetInstructionParent(slot), LLVMGetNextInstruction(slot));
+    LLVMBuildStore(builder, LLVMConstNull(ctx.llvm_ptr), slot);
+    LLVMDisposeBuilder(builder);
+
+    slot
+}
+
+/// Allocate stack memory in the entry block of the current function, so it is allocated once,
+/// even if the current block is executed many times.
+unsafe fn build_entry_alloca(ctx: &mut Context, ty: LLVMTypeRef, name: &str) -> LLVMValueRef {
+    let func = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctx.llvm_builder));
+    let entry = LLVMGetEntryBasicBlock(func);
+    let builder = LLVMCreateBuilderInContext(ctx.llvm_ctx);
+    let first = LLVMGetFirstInstruction(entry);
+
+    if first.is_null() {
+        LLVMPositionBuilderAtEnd(builder, entry);
+    } else {
+        LLVMPositionBuilderBefore(builder, first);
+    }
+
+    let slot = LLVMBuildAlloca(builder, ty, CString::new(name).unwrap().as_ptr());
+    LLVMDisposeBuilder(builder);
+
+    slot
+}
+
+unsafe fn build_local_get(ctx: &mut Context, slot: LLVMValueRef) -> LLVMValueRef {
+    LLVMBuildLoad(ctx.llvm_builder, slot, b"__local\0".as_ptr() as *const _)
+}
+
 fn build_global_get(ctx: &mut Context, name: LLVMValueRef) -> LLVMValueRef {
     debug!(target: "runjit.build", "build_global_get >>");
-    let get_global = ctx.extern_functions.get("__global_get_func").unwrap();
-    let value_delete = ctx.extern_functions.get("__value_delete").unwrap();
-    let args = vec![ctx.llvm_ctx_ptr, name];
-    let delete_args = vec![name];
+    let get_global = ctx.extern_functions.get("__global_get").unwrap();
+    let args = [ctx.llvm_ctx_ptr, name];
 
     unsafe {
-        let func = LLVMBuildCall(
+        let val = LLVMBuildCall(
             ctx.llvm_builder,
             get_global.0,
             args.as_ptr() as *mut _,
             args.len() as u32,
-            b"global_get_func\0".as_ptr() as *const _,
+            b"global_get\0".as_ptr() as *const _,
         );
 
-        LLVMBuildCall(
-            ctx.llvm_builder,
-            value_delete.0,
-            delete_args.as_ptr() as *mut LLVMValueRef,
-            delete_args.len() as u32,
-            b"__value_delete\0".as_ptr() as *const _,
-        );
+        build_delete(ctx, name);
         debug!(target: "runjit.build", "build_global_get <<");
-        func
+        val
     }
 }
 
 fn build_global_set(ctx: &mut Context, name: LLVMValueRef, value: LLVMValueRef) -> LLVMValueRef {
     debug!(target: "runjit.build", "build_global_set >>");
     let global_set = ctx.extern_functions.get("__global_set").unwrap();
-    let value_delete = ctx.extern_functions.get("__value_delete").unwrap();
-    let args = vec![ctx.llvm_ctx_ptr, name, value];
-    let delete_args = vec![name];
+    let args = [ctx.llvm_ctx_ptr, name, value];
 
     unsafe {
         let ret = LLVMBuildCall(
@@ -181,127 +298,69 @@ fn build_global_set(ctx: &mut Context, name: LLVMValueRef, value: LLVMValueRef)
             b"__global_set\0".as_ptr() as *const _,
         );
 
-        LLVMBuildCall(
-            ctx.llvm_builder,
-            value_delete.0,
-            delete_args.as_ptr() as *mut LLVMValueRef,
-            delete_args.len() as u32,
-            b"__value_delete\0".as_ptr() as *const _,
-        );
+        build_delete(ctx, name);
+        build_delete(ctx, value);
         debug!(target: "runjit.build", "build_global_set <<");
         ret
     }
 }
 
-unsafe fn const_op(ctx: &mut Context, left_ref: LLVMValueRef, right_ref: LLVMValueRef, op: Pair<Rule>) -> LLVMValueRef {
-    match op.as_rule() {
-        Rule::op_add => {
-            LLVMBuildFAdd(
-                ctx.llvm_builder,
-                left_ref,
-                right_ref,
-                b"exp_add\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_sub => {
-            LLVMBuildFSub(
-                ctx.llvm_builder,
-                left_ref,
-                right_ref,
-                b"exp_sub\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_mul => {
-            LLVMBuildFMul(
-                ctx.llvm_builder,
-                left_ref,
-                right_ref,
-                b"exp_mul\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_div => {
-            LLVMBuildFDiv(
-                ctx.llvm_builder,
-                left_ref,
-                right_ref,
-                b"exp_div\0".as_ptr() as *const _,
-            )
-        }
-        // Rule::op_mod => Operation::Mod,
-        Rule::op_and => {
-            LLVMBuildAnd(
-                ctx.llvm_builder,
-                left_ref,
-                right_ref,
-                b"exp_and\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_or => {
-            LLVMBuildOr(
-                ctx.llvm_builder,
-                left_ref,
-                right_ref,
-                b"exp_or\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_eq => {
-            LLVMBuildFCmp(
-                ctx.llvm_builder,
-                llvm::LLVMRealPredicate::LLVMRealOEQ,
-                left_ref,
-                right_ref,
-                b"exp_oeq\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_neq => {
-            LLVMBuildFCmp(
-                ctx.llvm_builder,
-                llvm::LLVMRealPredicate::LLVMRealONE,
-                left_ref,
-                right_ref,
-                b"exp_one\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_gt => {
-            LLVMBuildFCmp(
-                ctx.llvm_builder,
-                llvm::LLVMRealPredicate::LLVMRealOGT,
-                left_ref,
-                right_ref,
-                b"exp_ogt\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_le => {
-            LLVMBuildFCmp(
-                ctx.llvm_builder,
-                llvm::LLVMRealPredicate::LLVMRealOLT,
-                left_ref,
-                right_ref,
-                b"exp_olt\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_gte => {
-            LLVMBuildFCmp(
-                ctx.llvm_builder,
-                llvm::LLVMRealPredicate::LLVMRealOGE,
-                left_ref,
-                right_ref,
-                b"exp_oge\0".as_ptr() as *const _,
-            )
-        }
-        Rule::op_lee => {
-            LLVMBuildFCmp(
-                ctx.llvm_builder,
-                llvm::LLVMRealPredicate::LLVMRealOLE,
-                left_ref,
-                right_ref,
-                b"exp_ole\0".as_ptr() as *const _,
-            )
-        }
-        _ => panic!("unknown operation in expression: {:?}", op.as_rule()),
+/// Read the path `name` inside of the borrowed local value `root`, see `callbacks::value_get`.
+fn build_value_get(ctx: &mut Context, root: LLVMValueRef, name: LLVMValueRef) -> LLVMValueRef {
+    debug!(target: "runjit.build", "build_value_get");
+    let value_get = ctx.extern_functions.get("__value_get").unwrap();
+    let args = [ctx.llvm_ctx_ptr, root, name];
+
+    unsafe {
+        let val = LLVMBuildCall(
+            ctx.llvm_builder,
+            value_get.0,
+            args.as_ptr() as *mut _,
+            args.len() as u32,
+            b"value_get\0".as_ptr() as *const _,
+        );
+
+        build_delete(ctx, name);
+        val
     }
 }
 
+fn build_value_set(ctx: &mut Context, root: LLVMValueRef, name: LLVMValueRef, value: LLVMValueRef) -> LLVMValueRef {
+    debug!(target: "runjit.build", "build_value_set");
+    let value_set = ctx.extern_functions.get("__value_set").unwrap();
+    let args = [ctx.llvm_ctx_ptr, root, name, value];
+
+    unsafe {
+        let ret = LLVMBuildCall(
+            ctx.llvm_builder,
+            value_set.0,
+            args.as_ptr() as *mut LLVMValueRef,
+            args.len() as u32,
+            b"__value_set\0".as_ptr() as *const _,
+        );
+
+        build_delete(ctx, name);
+        build_delete(ctx, value);
+        ret
+    }
+}
+
+/// Box an `i1`, like a folded `true` or `false`, into a `Bool`.
+unsafe fn build_bool(ctx: &mut Context, val: LLVMValueRef) -> LLVMValueRef {
+    let i8_t = LLVMInt8TypeInContext(ctx.llvm_ctx);
+    let byte = LLVMBuildZExt(ctx.llvm_builder, val, i8_t, b"__bool_byte\0".as_ptr() as *const _);
+    let bool_new = ctx.extern_functions.get("__bool_new").unwrap();
+    let args = [byte];
+
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        bool_new.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"__bool_new\0".as_ptr() as *const _,
+    )
+}
+
 unsafe fn generic_op(ctx: &mut Context, left_ref: LLVMValueRef, right_ref: LLVMValueRef, op: Pair<Rule>) -> LLVMValueRef {
     debug!(target: "runjit.build", "generic_op");
     let call = match op.as_rule() {
@@ -309,9 +368,7 @@ unsafe fn generic_op(ctx: &mut Context, left_ref: LLVMValueRef, right_ref: LLVMV
         Rule::op_sub => ctx.extern_functions.get("__sub").unwrap(),
         Rule::op_mul => ctx.extern_functions.get("__mul").unwrap(),
         Rule::op_div => ctx.extern_functions.get("__div").unwrap(),
-        // Rule::op_mod => Operation::Mod,
-        Rule::op_and => ctx.extern_functions.get("__and").unwrap(),
-        Rule::op_or => ctx.extern_functions.get("__or").unwrap(),
+        Rule::op_mod => ctx.extern_functions.get("__mod").unwrap(),
         Rule::op_eq => ctx.extern_functions.get("__eq").unwrap(),
         Rule::op_neq => ctx.extern_functions.get("__neq").unwrap(),
         Rule::op_gt => ctx.extern_functions.get("__gt").unwrap(),
@@ -321,56 +378,259 @@ unsafe fn generic_op(ctx: &mut Context, left_ref: LLVMValueRef, right_ref: LLVMV
         _ => panic!("unknown operation in expression: {:?}", op.as_rule()),
     };
 
-    let args = vec![left_ref, right_ref];
-    LLVMBuildCall(
+    // arithmetic reports overflow and division by zero to the context
+    let args = match op.as_rule() {
+        Rule::op_add | Rule::op_sub | Rule::op_mul | Rule::op_div | Rule::op_mod => {
+            vec![ctx.llvm_ctx_ptr, left_ref, right_ref]
+        }
+        _ => vec![left_ref, right_ref],
+    };
+    let res = LLVMBuildCall(
         ctx.llvm_builder,
         call.0,
         args.as_ptr() as *mut LLVMValueRef,
         args.len() as u32,
         b"__op_res\0".as_ptr() as *const _,
-    )
+    );
+
+    build_delete(ctx, left_ref);
+    build_delete(ctx, right_ref);
+
+    res
 }
 
-unsafe fn exp(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
-    debug!(target: "runjit.build", "exp >>");
-    let mut inner = pair.into_inner();
+/// A literal, or prefix operators applied to one, known while building.
+enum Const {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+}
 
-    let next = inner.next().unwrap();
-    let rule = next.as_rule();
-    let left_ref = match rule {
-        Rule::exp => exp(ctx, next),
+/// Evaluate a literal number, bool or `null`, with any prefix operators and parentheses,
+/// while building. `None` for anything else, or if an operator fails, which is left
+/// to the runtime to report.
+fn fold(ctx: &Context, pair: &Pair<Rule>) -> Result<Option<Const>, Error> {
+    match pair.as_rule() {
         Rule::literal => {
-            let inner = next.into_inner().next().unwrap();
+            // `null` is the only literal without an inner rule
+            let inner = match pair.clone().into_inner().next() {
+                Some(inner) => inner,
+                None => return Ok(Some(Const::Null)),
+            };
 
             match inner.as_rule() {
                 Rule::numeric => {
-                    let s = inner.as_str().trim();
-                    // LLVMConstRealOfString(ctx.llvm_f64, CString::new(s).unwrap().as_ptr())
-
-                    let float_new = ctx.extern_functions.get("__float_new").unwrap();
-                    let args = vec![LLVMConstReal(ctx.llvm_f64, s.parse().unwrap())];
-                    LLVMBuildCall(
-                        ctx.llvm_builder,
-                        float_new.0,
-                        args.as_ptr() as *mut LLVMValueRef,
-                        args.len() as u32,
-                        b"__float_new\0".as_ptr() as *const _,
-                    )
-                }
-                Rule::string_literal => {
-                    let s = inner.as_str();
-                    build_string(ctx, s.slice_unchecked(1, s.len() - 1))
+                    let num = inner.into_inner().next().unwrap();
+
+                    match num.as_rule() {
+                        Rule::integer => int(ctx, pair, num.as_str()).map(Some),
+                        _ => Ok(Some(Const::Float(num.as_str().parse().unwrap()))),
+                    }
                 }
-                _ => panic!("not supported yet"),
+                Rule::boolean => Ok(Some(Const::Bool(inner.as_str() == "true"))),
+                _ => Ok(None),
+            }
+        }
+        Rule::unary => {
+            if let Some(s) = negative_integer(pair) {
+                return int(ctx, pair, &s).map(Some);
+            }
+
+            let mut inner = pair.clone().into_inner();
+            let op = inner.next().unwrap();
+            let operand = inner.next().unwrap();
+
+            let res = match (op.as_rule(), fold(ctx, &operand)?) {
+                (Rule::op_neg, Some(Const::Int(i))) => i.checked_neg().map(Const::Int),
+                (Rule::op_neg, Some(Const::Float(f))) => Some(Const::Float(-f)),
+                (Rule::op_pos, Some(Const::Int(i))) => Some(Const::Int(i)),
+                (Rule::op_pos, Some(Const::Float(f))) => Some(Const::Float(f)),
+                (Rule::op_not, Some(c)) => Some(Const::Bool(!match c {
+                    Const::Int(i) => i != 0,
+                    Const::Float(f) => f != 0.0 && !f.is_nan(),
+                    Const::Bool(b) => b,
+                    Const::Null => false,
+                })),
+                _ => None,
+            };
+
+            Ok(res)
+        }
+        Rule::exp => {
+            let mut inner = pair.clone().into_inner();
+
+            match (inner.next(), inner.next()) {
+                (Some(term), None) => fold(ctx, &term),
+                _ => Ok(None),
             }
         }
+        _ => Ok(None),
+    }
+}
+
+/// The integer literal `s`, which is found at `pair`, see `parser::integer`.
+fn int(ctx: &Context, pair: &Pair<Rule>, s: &str) -> Result<Const, Error> {
+    integer(s).map(Const::Int).map_err(|msg| Error::Compile(location(ctx, pair), msg))
+}
+
+unsafe fn build_const(ctx: &mut Context, c: Const) -> LLVMValueRef {
+    let (name, arg) = match c {
+        Const::Int(i) => ("__int_new", LLVMConstInt(LLVMInt64TypeInContext(ctx.llvm_ctx), i as u64, 1)),
+        Const::Float(f) => ("__float_new", LLVMConstReal(ctx.llvm_f64, f)),
+        Const::Bool(b) => return build_bool(ctx, LLVMConstInt(LLVMInt1TypeInContext(ctx.llvm_ctx), b as u64, 0)),
+        Const::Null => return build_null(ctx),
+    };
+
+    let new = ctx.extern_functions.get(name).unwrap();
+    let args = [arg];
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        new.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"__number_new\0".as_ptr() as *const _,
+    )
+}
+
+/// Apply a prefix operator at runtime, see `callbacks::neg`, `pos` and `not`.
+unsafe fn unary(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "unary");
+    let mut inner = pair.into_inner();
+    let op = inner.next().unwrap();
+    let val = term(ctx, inner.next().unwrap())?;
+
+    let (name, args) = match op.as_rule() {
+        Rule::op_neg => ("__neg", vec![ctx.llvm_ctx_ptr, val]),
+        Rule::op_pos => ("__pos", vec![ctx.llvm_ctx_ptr, val]),
+        Rule::op_not => ("__not", vec![val]),
+        _ => panic!("unknown prefix operator: {:?}", op.as_rule()),
+    };
+
+    let func = ctx.extern_functions.get(name).unwrap();
+    let res = LLVMBuildCall(
+        ctx.llvm_builder,
+        func.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"__unary\0".as_ptr() as *const _,
+    );
+
+    build_delete(ctx, val);
+
+    Ok(res)
+}
+
+/// An operand of `exp`, built when it is needed, so `&&` and `||` can skip their right side.
+type Operand<'i> = Box<dyn FnOnce(&mut Context) -> Result<LLVMValueRef, Error> + 'i>;
+
+unsafe fn exp<'i>(ctx: &mut Context, pair: Pair<'i, Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "exp >>");
+
+    let build = climb(
+        ctx,
+        pair,
+        |_, p| -> Operand<'i> { Box::new(move |ctx: &mut Context| term(ctx, p)) },
+        |_, left: Operand<'i>, op: Pair<'i, Rule>, right: Operand<'i>| -> Operand<'i> {
+            Box::new(move |ctx: &mut Context| match op.as_rule() {
+                Rule::op_and | Rule::op_or => logic_op(ctx, left, right, op),
+                _ => {
+                    let left = left(ctx)?;
+                    let right = right(ctx)?;
+                    Ok(generic_op(ctx, left, right, op))
+                }
+            })
+        },
+    );
+    let res = build(ctx);
+
+    debug!(target: "runjit.build", "exp <<");
+
+    res
+}
+
+/// Build `&&` or `||`, the right side is only evaluated if the left one does not decide the result.
+unsafe fn logic_op(ctx: &mut Context, left: Operand, right: Operand, op: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "logic_op");
+    let is_and = op.as_rule() == Rule::op_and;
+
+    let left = left(ctx)?;
+    let left = build_test(ctx, left);
+    let left_bb = LLVMGetInsertBlock(ctx.llvm_builder);
+
+    let func = LLVMGetBasicBlockParent(left_bb);
+    let right_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__logic_right\0".as_ptr() as *const _);
+    let end_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__logic_end\0".as_ptr() as *const _);
+
+    if is_and {
+        LLVMBuildCondBr(ctx.llvm_builder, left, right_bb, end_bb);
+    } else {
+        LLVMBuildCondBr(ctx.llvm_builder, left, end_bb, right_bb);
+    }
+
+    LLVMPositionBuilderAtEnd(ctx.llvm_builder, right_bb);
+    *ctx.block_stack.last_mut().unwrap() = right_bb;
+
+    let right = right(ctx)?;
+    let right = build_test(ctx, right);
+    let right_end_bb = LLVMGetInsertBlock(ctx.llvm_builder);
+    LLVMBuildBr(ctx.llvm_builder, end_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.llvm_builder, end_bb);
+    *ctx.block_stack.last_mut().unwrap() = end_bb;
+
+    // skipping the right side means false for `&&` and true for `||`
+    let res = LLVMBuildPhi(ctx.llvm_builder, LLVMInt1TypeInContext(ctx.llvm_ctx), b"__logic\0".as_ptr() as *const _);
+    let mut values = [LLVMConstInt(LLVMInt1TypeInContext(ctx.llvm_ctx), !is_and as u64, 0), right];
+    let mut blocks = [left_bb, right_end_bb];
+    LLVMAddIncoming(res, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+
+    Ok(build_bool(ctx, res))
+}
+
+unsafe fn term(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    let rule = pair.as_rule();
+
+    let val = match rule {
+        Rule::exp => exp(ctx, pair)?,
+        Rule::literal => match fold(ctx, &pair)? {
+            Some(c) => build_const(ctx, c),
+            None => {
+                // the only literal which can not be folded
+                let loc = location(ctx, &pair);
+                let s = pair.as_str();
+                let s = unescape(&s[1..s.len() - 1]).map_err(|msg| Error::Compile(loc, msg))?;
+                build_string(ctx, &s)
+            }
+        },
+        Rule::unary => match fold(ctx, &pair)? {
+            Some(c) => build_const(ctx, c),
+            None => unary(ctx, pair)?,
+        },
+        Rule::call => call(ctx, pair)?,
+        Rule::lambda => lambda(ctx, pair)?,
+        Rule::array => array(ctx, pair)?,
+        Rule::dict => dict(ctx, pair)?,
         Rule::access => {
-            match access(ctx, next) {
-                AccessToken::Pure(name) => {
-                    debug!(target: "runjit.build", "  pure access");
-                    *ctx.local_stack.last().unwrap().get(&name).unwrap()
+            match access(ctx, pair)? {
+                AccessToken::Pure(_, Scope::Local(slot)) => {
+                    debug!(target: "runjit.build", "  local access");
+                    let val = build_local_get(ctx, slot);
+                    build_clone(ctx, val)
+                }
+                AccessToken::Pure(name, Scope::Global) => {
+                    debug!(target: "runjit.build", "  global access");
+                    let val = build_string(ctx, &name);
+                    let name = build_access_array(ctx, &vec![val]);
+                    build_global_get(ctx, name)
                 }
-                AccessToken::Parts(parts) => {
+                AccessToken::Parts(parts, Scope::Local(slot)) => {
+                    debug!(target: "runjit.build", "  local parts access");
+                    let name = build_access_array(ctx, &parts);
+                    let root = build_local_get(ctx, slot);
+                    build_value_get(ctx, root, name)
+                }
+                AccessToken::Parts(parts, Scope::Global) => {
                     debug!(target: "runjit.build", "  parts access");
                     let name = build_access_array(ctx, &parts);
                     build_global_get(ctx, name)
@@ -380,57 +640,64 @@ unsafe fn exp(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
         _ => panic!("unknown exp: {:?}", rule),
     };
 
-    debug!(target: "runjit.build", "exp <<");
-
-    if let Some(op) = inner.next() {
-        if let Some(right) = inner.next() {
-            let right_ref = exp(ctx, right);
-            // TODO: Use more const op, to make things FAAAAAST!!!
-            // const_op(ctx, left_ref, right_ref, op)
-            generic_op(ctx, left_ref, right_ref, op)
-        } else {
-            panic!("incomplete expression")
-        }
-    } else {
-        left_ref
-    }
-
+    Ok(val)
 }
 
-fn assign(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+fn assign(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "assign >>");
     let mut inner = pair.into_inner();
 
     let v = inner.next().unwrap();
+    let loc = location(ctx, &v);
     let access_token = match v.as_rule() {
-        Rule::access => access(ctx, v),
+        Rule::access => access(ctx, v)?,
         _ => panic!("expected access"),
     };
 
-    let e = inner.next().unwrap();
-    let ex = match e.as_rule() {
-        Rule::exp => unsafe { exp(ctx, e) }
-        Rule::lambda => lambda(ctx, e),
-        Rule::dict => dict(ctx, e),
-        Rule::array => array(ctx, e),
-        _ => panic!("unexpected assign: {:?}", e.as_rule()),
-    };
+    // captured values are copies, assigning to one would not change the enclosing variable
+    if let AccessToken::Pure(ref name, Scope::Local(_)) = access_token {
+        if ctx.capture_stack.last().is_some_and(|c| c.contains(name)) {
+            return Err(Error::Compile(
+                loc,
+                format!("can not assign to `{}`, it is captured from an enclosing lambda", name),
+            ));
+        }
+    }
+
+    let ex = unsafe { exp(ctx, inner.next().unwrap())? };
 
     let ret = match access_token {
-        AccessToken::Pure(name) => {
-            debug!(target: "runjit.build", "  pure {}", ctx.local_stack.len());
-            if ctx.local_stack.len() > 0 {
-                ctx.local_stack.last_mut().unwrap().insert(name, ex);
-
-                ex
-            } else {
-                let nurf = unsafe { build_string(ctx, &name) };
-                let parts = vec![nurf];
-                let ident_array = build_access_array(ctx, &parts);
-                build_global_set(ctx, ident_array, ex)
-            }
+        AccessToken::Pure(name, Scope::Global) if ctx.local_stack.is_empty() => {
+            let nurf = unsafe { build_string(ctx, &name) };
+            let parts = vec![nurf];
+            let ident_array = build_access_array(ctx, &parts);
+            build_global_set(ctx, ident_array, ex)
         }
-        AccessToken::Parts(parts) => {
+        AccessToken::Pure(name, scope) => unsafe {
+            let slot = match scope {
+                Scope::Local(slot) => slot,
+                // inside of lambdas assigning to a new name creates a local
+                Scope::Global => {
+                    let slot = build_local(ctx, &name);
+                    ctx.local_stack.last_mut().unwrap().insert(name, slot);
+                    slot
+                }
+            };
+
+            // the old value is released, the same code may run again in a loop
+            let old = build_local_get(ctx, slot);
+            LLVMBuildStore(ctx.llvm_builder, ex, slot);
+            build_delete(ctx, old);
+
+            ex
+        },
+        AccessToken::Parts(parts, Scope::Local(slot)) => {
+            debug!(target: "runjit.build", "  local parts");
+            let ident_array = build_access_array(ctx, &parts);
+            let root = unsafe { build_local_get(ctx, slot) };
+            build_value_set(ctx, root, ident_array, ex)
+        }
+        AccessToken::Parts(parts, Scope::Global) => {
             debug!(target: "runjit.build", "  parts");
             let ident_array = build_access_array(ctx, &parts);
             build_global_set(ctx, ident_array, ex)
@@ -439,15 +706,20 @@ fn assign(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
 
     debug!(target: "runjit.build", "assign <<");
 
-    ret
+    Ok(ret)
 }
 
-fn lambda(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+/// Build a lambda as a function taking its environment first and its params after,
+/// resulting in a `Value::Closure`.
+///
+/// The variables the lambda uses from enclosing lambdas are captured, see `resolve`,
+/// their values are copied into the environment when the closure is created.
+fn lambda(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "lambda >> {}", pair.as_str());
     let mut inner: Vec<Pair<Rule>> = pair.into_inner().collect();
     let mut params = Vec::new();
     let mut param_refs = BTreeMap::new();
-    let mut args = Vec::new();
+    let mut args = vec![ctx.llvm_ptr];
 
     if inner.len() > 1 {
         debug!(target: "runjit.build", "params >> {}", inner[0].as_str());
@@ -461,172 +733,660 @@ fn lambda(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
         let ftype = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
         let func = LLVMAddFunction(ctx.llvm_module, b"__lambda\0".as_ptr() as *const _, ftype);
 
-        for i in 0..params.len() {
-            let val = LLVMGetParam(func, i as u32);
-            param_refs.insert(params[i].clone(), val);
+        let entry_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__entry\0".as_ptr() as *const _ );
+        let bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__body\0".as_ptr() as *const _ );
+        let exit_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__exit\0".as_ptr() as *const _ );
+        LLVMPositionBuilderAtEnd(ctx.llvm_builder, entry_bb);
+        ctx.block_stack.push(entry_bb);
+
+        let ret_slot = build_local(ctx, "__ret");
+
+        // the environment comes first
+        for (i, param) in params.iter().enumerate() {
+            let val = LLVMGetParam(func, i as u32 + 1);
+            let slot = build_local(ctx, param);
+            let val = build_clone(ctx, val);
+            LLVMBuildStore(ctx.llvm_builder, val, slot);
+            param_refs.insert(param.clone(), slot);
         }
 
-        let bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__entry\0".as_ptr() as *const _ );
+        // captured variables are loaded at the end of the entry block, see `resolve`
+        LLVMBuildBr(ctx.llvm_builder, bb);
         LLVMPositionBuilderAtEnd(ctx.llvm_builder, bb);
+        *ctx.block_stack.last_mut().unwrap() = bb;
 
         ctx.local_stack.push(param_refs);
-        ctx.block_stack.push(bb);
+        ctx.capture_stack.push(Vec::new());
+        ctx.return_stack.push((exit_bb, ret_slot));
+
+        // loops of the enclosing function are out of reach for `break` and `continue`
+        let loops = mem::take(&mut ctx.loop_stack);
 
         let blk = inner.remove(0);
 
-        let last = block(ctx, blk);
+        let res = block(ctx, blk);
+
+        ctx.loop_stack = loops;
+        ctx.return_stack.pop();
+        res?;
 
         debug!(target: "runjit.build", "  build ret");
 
-        LLVMBuildRet(ctx.llvm_builder, last);
+        if LLVMGetBasicBlockTerminator(LLVMGetInsertBlock(ctx.llvm_builder)).is_null() {
+            let null = build_null(ctx);
+            LLVMBuildStore(ctx.llvm_builder, null, ret_slot);
+            LLVMBuildBr(ctx.llvm_builder, exit_bb);
+        }
+
+        // every way out of the lambda goes through the exit block, which releases the locals
+        LLVMMoveBasicBlockAfter(exit_bb, LLVMGetLastBasicBlock(func));
+        LLVMPositionBuilderAtEnd(ctx.llvm_builder, exit_bb);
+
+        let slots: Vec<LLVMValueRef> = ctx.local_stack.last().unwrap().values().cloned().collect();
+
+        for slot in slots {
+            let val = build_local_get(ctx, slot);
+            build_delete(ctx, val);
+        }
+
+        let ret = build_local_get(ctx, ret_slot);
+        LLVMBuildRet(ctx.llvm_builder, ret);
 
         ctx.block_stack.pop();
         ctx.local_stack.pop();
+        let captures = ctx.capture_stack.pop().unwrap();
         LLVMPositionBuilderAtEnd(ctx.llvm_builder, ctx.block_stack[ctx.block_stack.len() - 1]);
 
+        // the captured variables are locals of the enclosing lambda, or captured by it in turn
+        let mut env = Vec::new();
+
+        for name in captures {
+            let val = match resolve(ctx, &name) {
+                Scope::Local(slot) => build_local_get(ctx, slot),
+                Scope::Global => panic!("captured `{}` is not a local", name),
+            };
+            env.push(build_clone(ctx, val));
+        }
+
+        let env = build_access_array(ctx, &env);
+
         debug!(target: "runjit.build", "  cast func ptr {:?} {:?}", LLVMGetValueKind(func), LLVMGetTypeKind(ctx.llvm_ptr));
 
-        // let ptr = LLVMBuildPtrToInt(ctx.llvm_builder, func, ctx.llvm_ptr, b"__lambda_address\0".as_ptr() as *const _);
         let ptr = LLVMBuildBitCast(ctx.llvm_builder, func, ctx.llvm_ptr, b"__lambda_address\0".as_ptr() as *const _);
-        let lambda_new = ctx.extern_functions.get("__lambda_new").unwrap();
-        let args = vec![ptr];
+        let closure_new = ctx.extern_functions.get("__closure_new").unwrap();
+        let args = [ptr, env];
 
-        debug!(target: "runjit.build", "lambda <<");
-
-        LLVMBuildCall(
+        let closure = LLVMBuildCall(
             ctx.llvm_builder,
-            lambda_new.0,
+            closure_new.0,
             args.as_ptr() as *mut LLVMValueRef,
             args.len() as u32,
-            b"__lambda_address\0".as_ptr() as *const _,
-        )
-    }
+            b"__closure\0".as_ptr() as *const _,
+        );
+
+        build_delete(ctx, env);
 
+        debug!(target: "runjit.build", "lambda <<");
+
+        Ok(closure)
+    }
 }
 
-fn call(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+/// Build a call, every further argument list calls the result of the one before,
+/// like `f(1)(2)`.
+fn call(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "call >>");
+    let start = pair.clone().into_span().start();
+    let source = pair.as_str();
     let mut call = pair.into_inner();
-    let mut params = Vec::new();
-    let mut args = Vec::new();
 
-    let access_token = access(ctx, call.next().unwrap());
+    let callee = call.next().unwrap();
+    let loc = location(ctx, &callee);
+    let access_token = access(ctx, callee)?;
+    let params = call_args(ctx, call.next().unwrap())?;
+    let mut res = call_access(ctx, loc, access_token, params)?;
+
+    for args in call {
+        // the source of the callee, like `f(1)`, for errors
+        let callee = source[..args.clone().into_span().start() - start].trim_end();
+        debug!(target: "runjit.build", "  call result of '{}'", callee);
+        let params = call_args(ctx, args)?;
+
+        unsafe {
+            let val = build_string(ctx, callee);
+            let name = build_access_array(ctx, &vec![val]);
+            let next = build_lambda_call(ctx, res, name, &params);
+
+            build_delete(ctx, name);
+            build_delete(ctx, res);
+
+            for p in params {
+                build_delete(ctx, p);
+            }
+
+            res = next;
+        }
+    }
 
+    debug!(target: "runjit.build", "call <<");
+
+    Ok(res)
+}
+
+/// Evaluate the arguments of a `call_args` rule.
+fn call_args(ctx: &mut Context, pair: Pair<Rule>) -> Result<Vec<LLVMValueRef>, Error> {
     debug!(target: "runjit.build", "  params");
+    let mut params = Vec::new();
 
-    if let Some(ps) = call.next() {
-        for mut param in ps.into_inner() {
+    if let Some(ps) = pair.into_inner().next() {
+        for param in ps.into_inner() {
             match param.as_rule() {
                 Rule::exp => {
-                    args.push(ctx.llvm_ptr);
-                    params.push(unsafe { exp(ctx, param) } );
+                    params.push(unsafe { exp(ctx, param)? });
                 },
                 _ => panic!("unexpected rule: {:?}", param.as_rule()),
             }
         }
     }
 
+    Ok(params)
+}
+
+/// Call the lambda at an access with the evaluated `params`, which are used up.
+///
+/// Native functions called directly are checked for the number of arguments while building,
+/// `loc` is the location of the callee.
+fn call_access(ctx: &mut Context, loc: Location, access_token: AccessToken, params: Vec<LLVMValueRef>) -> Result<LLVMValueRef, Error> {
     for p in &params {
         debug!(target: "runjit.build", "param type {:?}",unsafe { LLVMGetValueKind(*p) });
     }
 
-    let func_ptr = match access_token {
-        AccessToken::Pure(name) => {
-            debug!(target: "runjit.build", "  call pure '{}'", name);
-            let val = ctx.local_stack.last().and_then(|v| match v.get(&name) {
-                Some(var) => Some(*var),
-                None => {
-                    let val = { debug!(target: "runjit.build", "  extern func {}", name); ctx.extern_functions.get(&name).clone() };
-                    match val {
-                        Some(efunc) => Some(efunc.0),
-                        None => {
-                            None
-                        }
-                    }
+    // host functions check their arguments when called, so they get the actual argument count
+    if let AccessToken::Pure(ref name, Scope::Global) = access_token {
+        if let Some(id) = ctx.host_function(name) {
+            debug!(target: "runjit.build", "  host func {}", name);
+
+            unsafe {
+                let res = build_host_call(ctx, id, &params);
+
+                for p in params {
+                    build_delete(ctx, p);
                 }
-            });
 
-            match val {
-                Some(v) => v,
-                None => {
-                    let val = unsafe { build_string(ctx, &name) };
-                    let name = build_access_array(ctx, &vec![val]);
-                    let func = build_global_get(ctx, name);
+                return Ok(res);
+            }
+        }
+    }
 
-                    unsafe {
-                        let ptr_type = LLVMPointerType(LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0), 0);
-                        // let ptr_type = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
-                        let func_ptr = LLVMBuildIntToPtr(ctx.llvm_builder, func, ptr_type, b"var_to_func\0".as_ptr() as *const _);
-                        // let func_ptr = LLVMBuildBitCast(ctx.llvm_builder, func, ptr_type, b"var_to_func\0".as_ptr() as *const _);
+    if let AccessToken::Pure(ref name, Scope::Global) = access_token {
+        // internal functions are not visible to scripts
+        if !ctx.local_stack.is_empty() && !name.starts_with("__") && ctx.extern_functions.contains_key(name) {
+            debug!(target: "runjit.build", "  extern func {}", name);
+            let func = ctx.extern_functions[name].0;
 
-                        func_ptr
-                    }
+            unsafe {
+                let arity = LLVMCountParams(func) as usize;
+
+                if arity != params.len() {
+                    return Err(Error::Compile(loc, format!(
+                        "`{}` takes {} arguments, {} given",
+                        name,
+                        arity,
+                        params.len()
+                    )));
+                }
+
+                let res = LLVMBuildCall(
+                    ctx.llvm_builder,
+                    func,
+                    params.as_ptr() as *mut _,
+                    params.len() as u32,
+                    b"call\0".as_ptr() as *const _,
+                );
+                let res = build_result(ctx, res);
+
+                for p in params {
+                    build_delete(ctx, p);
                 }
+
+                return Ok(res);
             }
         }
-        AccessToken::Parts(parts) => {
-            debug!(target: "runjit.build", "  parts");
+    }
+
+    // the callee is kept until the call returns, so the environment of a closure stays valid,
+    // `name` is only needed for errors
+    let (callee, name) = match access_token {
+        AccessToken::Pure(name, Scope::Local(slot)) => unsafe {
+            debug!(target: "runjit.build", "  call local '{}'", name);
+            let val = build_string(ctx, &name);
+            let name = build_access_array(ctx, &vec![val]);
+            let local = build_local_get(ctx, slot);
+            (build_clone(ctx, local), name)
+        },
+        AccessToken::Pure(name, Scope::Global) => unsafe {
+            debug!(target: "runjit.build", "  call global '{}'", name);
+            let val = build_string(ctx, &name);
+            let name = build_access_array(ctx, &vec![val]);
+            let lookup = build_clone(ctx, name);
+            (build_global_get(ctx, lookup), name)
+        },
+        AccessToken::Parts(parts, Scope::Local(slot)) => unsafe {
+            debug!(target: "runjit.build", "  call local parts");
             let name = build_access_array(ctx, &parts);
-            let func = build_global_get(ctx, name);
-            debug!(target: "runjit.build", "  cast pointer");
-            unsafe {
-                let ptr_type = LLVMPointerType(LLVMFunctionType(ctx.llvm_ptr, params.as_ptr() as *mut _, params.len() as u32, 0), 0);
-                // let ptr_type = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
-                let func_ptr = LLVMBuildIntToPtr(ctx.llvm_builder, func, ptr_type, b"var_to_func\0".as_ptr() as *const _);
-                // let func_ptr = LLVMBuildBitCast(ctx.llvm_builder, func, ptr_type, b"var_to_func\0".as_ptr() as *const _);
+            let root = build_local_get(ctx, slot);
+            let lookup = build_clone(ctx, name);
+            (build_value_get(ctx, root, lookup), name)
+        },
+        AccessToken::Parts(parts, Scope::Global) => unsafe {
+            debug!(target: "runjit.build", "  call parts");
+            let name = build_access_array(ctx, &parts);
+            let lookup = build_clone(ctx, name);
+            (build_global_get(ctx, lookup), name)
+        },
+    };
+
+    unsafe {
+        let res = build_lambda_call(ctx, callee, name, &params);
+
+        build_delete(ctx, name);
+        build_delete(ctx, callee);
+
+        for p in params {
+            build_delete(ctx, p);
+        }
 
-                func_ptr
+        Ok(res)
+    }
+}
+
+/// Call the lambda value `callee` with `params`, passing the environment first if it is a
+/// `Value::Closure`, see `callbacks::callee`. `callee`, `name` and `params` are borrowed.
+/// Calling with the wrong number of arguments is a runtime error and results in `Null`.
+unsafe fn build_lambda_call(ctx: &mut Context, callee: LLVMValueRef, name: LLVMValueRef, params: &[LLVMValueRef]) -> LLVMValueRef {
+    let env_slot = build_entry_alloca(ctx, ctx.llvm_ptr, "__env");
+    let callee_fn = ctx.extern_functions.get("__callee").unwrap();
+    let argc = LLVMConstInt(LLVMInt64TypeInContext(ctx.llvm_ctx), params.len() as u64, 0);
+    let args = [ctx.llvm_ctx_ptr, callee, name, argc, env_slot];
+
+    let addr = LLVMBuildCall(
+        ctx.llvm_builder,
+        callee_fn.0,
+        args.as_ptr() as *mut _,
+        args.len() as u32,
+        b"__callee\0".as_ptr() as *const _,
+    );
+    let env = LLVMBuildLoad(ctx.llvm_builder, env_slot, b"__env\0".as_ptr() as *const _);
+    let is_lambda = LLVMBuildIsNull(ctx.llvm_builder, env, b"__is_lambda\0".as_ptr() as *const _);
+
+    let func = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctx.llvm_builder));
+    let lambda_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__call_lambda\0".as_ptr() as *const _);
+    let closure_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__call_closure\0".as_ptr() as *const _);
+    let end_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__call_end\0".as_ptr() as *const _);
+
+    LLVMBuildCondBr(ctx.llvm_builder, is_lambda, lambda_bb, closure_bb);
+
+    let mut results = Vec::new();
+
+    for &(bb, with_env) in &[(lambda_bb, false), (closure_bb, true)] {
+        LLVMPositionBuilderAtEnd(ctx.llvm_builder, bb);
+
+        let mut args: Vec<LLVMValueRef> = params.to_vec();
+
+        if with_env {
+            args.insert(0, env);
+        }
+
+        let types = vec![ctx.llvm_ptr; args.len()];
+        let ptr_type = LLVMPointerType(LLVMFunctionType(ctx.llvm_ptr, types.as_ptr() as *mut _, types.len() as u32, 0), 0);
+        let func_ptr = LLVMBuildIntToPtr(ctx.llvm_builder, addr, ptr_type, b"var_to_func\0".as_ptr() as *const _);
+
+        results.push(LLVMBuildCall(
+            ctx.llvm_builder,
+            func_ptr,
+            args.as_ptr() as *mut _,
+            args.len() as u32,
+            b"call\0".as_ptr() as *const _,
+        ));
+        LLVMBuildBr(ctx.llvm_builder, end_bb);
+    }
+
+    LLVMPositionBuilderAtEnd(ctx.llvm_builder, end_bb);
+    *ctx.block_stack.last_mut().unwrap() = end_bb;
+
+    let res = LLVMBuildPhi(ctx.llvm_builder, ctx.llvm_ptr, b"__result\0".as_ptr() as *const _);
+    let mut blocks = [lambda_bb, closure_bb];
+    LLVMAddIncoming(res, results.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+
+    build_result(ctx, res)
+}
+
+/// Build a conditional branch for `_if` and `_elseif` rules.
+///
+/// The condition is checked with the runtime truthiness of the value. Every `else if` gets its
+/// own conditional nested in the else block of its predecessor, so chains can be arbitrarily long.
+fn _if(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "if >>");
+    let mut the_if = pair.into_inner();
+
+    unsafe {
+        let func = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctx.llvm_builder));
+        let then_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__if_then\0".as_ptr() as *const _);
+        let end_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__if_end\0".as_ptr() as *const _);
+
+        let cond = build_truthy(ctx, the_if.next().unwrap())?;
+        let blk = the_if.next().unwrap();
+        let elsy = the_if.next();
+
+        let else_bb = match elsy {
+            Some(_) => LLVMInsertBasicBlockInContext(ctx.llvm_ctx, end_bb, b"__if_else\0".as_ptr() as *const _),
+            None => end_bb,
+        };
+
+        LLVMBuildCondBr(ctx.llvm_builder, cond, then_bb, else_bb);
+
+        build_branch(ctx, then_bb, end_bb, |ctx| block(ctx, blk))?;
+
+        if let Some(elsy) = elsy {
+            build_branch(ctx, else_bb, end_bb, |ctx| match elsy.as_rule() {
+                Rule::_else => block(ctx, elsy.into_inner().next().unwrap()),
+                Rule::_elseif => _if(ctx, elsy),
+                _ => panic!("unexpected else: {:?}", elsy.as_rule()),
+            })?;
+        }
+
+        LLVMPositionBuilderAtEnd(ctx.llvm_builder, end_bb);
+        *ctx.block_stack.last_mut().unwrap() = end_bb;
+
+        debug!(target: "runjit.build", "if <<");
+
+        Ok(LLVMConstNull(ctx.llvm_ptr))
+    }
+}
+
+/// Build a loop for the `_loop` rule.
+///
+/// The condition, if any, is checked before every iteration, without one the loop runs until
+/// it is left with `break` or `return`.
+fn _loop(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "loop >>");
+    let mut inner: Vec<Pair<Rule>> = pair.into_inner().collect();
+    let blk = inner.pop().unwrap();
+
+    unsafe {
+        let func = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctx.llvm_builder));
+        let cond_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__loop_cond\0".as_ptr() as *const _);
+        let body_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__loop_body\0".as_ptr() as *const _);
+        let end_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__loop_end\0".as_ptr() as *const _);
+
+        LLVMBuildBr(ctx.llvm_builder, cond_bb);
+        LLVMPositionBuilderAtEnd(ctx.llvm_builder, cond_bb);
+        *ctx.block_stack.last_mut().unwrap() = cond_bb;
+
+        match inner.pop() {
+            Some(e) => {
+                let cond = build_truthy(ctx, e)?;
+                LLVMBuildCondBr(ctx.llvm_builder, cond, body_bb, end_bb);
+            }
+            None => {
+                LLVMBuildBr(ctx.llvm_builder, body_bb);
             }
         }
+
+        ctx.loop_stack.push((cond_bb, end_bb));
+
+        let res = build_branch(ctx, body_bb, cond_bb, |ctx| block(ctx, blk));
+
+        ctx.loop_stack.pop();
+        res?;
+
+        LLVMPositionBuilderAtEnd(ctx.llvm_builder, end_bb);
+        *ctx.block_stack.last_mut().unwrap() = end_bb;
+
+        debug!(target: "runjit.build", "loop <<");
+
+        Ok(LLVMConstNull(ctx.llvm_ptr))
+    }
+}
+
+fn _break(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "break");
+    let end_bb = match ctx.loop_stack.last() {
+        Some(&(_, end_bb)) => end_bb,
+        None => return Err(Error::Compile(location(ctx, &pair), String::from("`break` outside of a loop"))),
     };
 
-    debug!(target: "runjit.build", "call <<");
+    unsafe {
+        build_jump(ctx, end_bb);
+        Ok(LLVMConstNull(ctx.llvm_ptr))
+    }
+}
+
+fn _continue(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "continue");
+    let cond_bb = match ctx.loop_stack.last() {
+        Some(&(cond_bb, _)) => cond_bb,
+        None => return Err(Error::Compile(location(ctx, &pair), String::from("`continue` outside of a loop"))),
+    };
 
     unsafe {
-        LLVMBuildCall(
+        build_jump(ctx, cond_bb);
+        Ok(LLVMConstNull(ctx.llvm_ptr))
+    }
+}
+
+/// Return from the current lambda, with `Null` if there is no expression.
+fn _return(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    debug!(target: "runjit.build", "return");
+
+    let (exit_bb, ret_slot) = match ctx.return_stack.last() {
+        Some(&ret) => ret,
+        None => return Err(Error::Compile(location(ctx, &pair), String::from("`return` outside of a lambda"))),
+    };
+
+    unsafe {
+        let val = match pair.into_inner().next() {
+            Some(e) => exp(ctx, e)?,
+            None => build_null(ctx),
+        };
+
+        LLVMBuildStore(ctx.llvm_builder, val, ret_slot);
+        build_jump(ctx, exit_bb);
+
+        Ok(LLVMConstNull(ctx.llvm_ptr))
+    }
+}
+
+/// A new reference to the shared `Null`, see `callbacks::null`.
+unsafe fn build_null(ctx: &mut Context) -> LLVMValueRef {
+    let null_new = ctx.extern_functions.get("__null_new").unwrap();
+
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        null_new.0,
+        ptr::null_mut(),
+        0,
+        b"__null_new\0".as_ptr() as *const _,
+    )
+}
+
+/// Call the host function `id` through `__host_call`, the arguments are borrowed.
+unsafe fn build_host_call(ctx: &mut Context, id: usize, args: &[LLVMValueRef]) -> LLVMValueRef {
+    let i64_t = LLVMInt64TypeInContext(ctx.llvm_ctx);
+    let argv = build_entry_alloca(ctx, LLVMArrayType(ctx.llvm_ptr, args.len() as u32), "__argv");
+
+    for (i, arg) in args.iter().enumerate() {
+        let mut indices = [LLVMConstInt(i64_t, 0, 0), LLVMConstInt(i64_t, i as u64, 0)];
+        let el = LLVMBuildGEP(
             ctx.llvm_builder,
-            func_ptr,
-            params.as_ptr() as *mut _,
-            params.len() as u32,
-            b"call\0".as_ptr() as *const _,
-        )
+            argv,
+            indices.as_mut_ptr(),
+            indices.len() as u32,
+            b"__arg\0".as_ptr() as *const _,
+        );
+
+        LLVMBuildStore(ctx.llvm_builder, *arg, el);
+    }
+
+    let host_call = ctx.extern_functions.get("__host_call").unwrap().0;
+    let argv = LLVMBuildBitCast(
+        ctx.llvm_builder,
+        argv,
+        LLVMPointerType(ctx.llvm_ptr, 0),
+        b"__argv_ptr\0".as_ptr() as *const _,
+    );
+    let mut call_args = [
+        ctx.llvm_ctx_ptr,
+        LLVMConstInt(i64_t, id as u64, 0),
+        LLVMConstInt(i64_t, args.len() as u64, 0),
+        argv,
+    ];
+
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        host_call,
+        call_args.as_mut_ptr(),
+        call_args.len() as u32,
+        b"__host_call\0".as_ptr() as *const _,
+    )
+}
+
+/// Add the function `name` for the host function `id`, so it can be used as a lambda value.
+pub unsafe fn host_function(ctx: &mut Context, name: &str, id: usize, arity: usize) {
+    debug!(target: "runjit.build", "host_function {}", name);
+    let mut args = vec![ctx.llvm_ptr; arity];
+    let ftype = LLVMFunctionType(ctx.llvm_ptr, args.as_mut_ptr(), arity as u32, 0);
+    let func = LLVMAddFunction(ctx.llvm_module, CString::new(name).unwrap().as_ptr(), ftype);
+    let bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__entry\0".as_ptr() as *const _);
+
+    LLVMPositionBuilderAtEnd(ctx.llvm_builder, bb);
+
+    let params: Vec<LLVMValueRef> = (0..arity).map(|i| LLVMGetParam(func, i as u32)).collect();
+    let res = build_host_call(ctx, id, &params);
+
+    LLVMBuildRet(ctx.llvm_builder, res);
+}
+
+/// Take a new reference to `val`.
+unsafe fn build_clone(ctx: &mut Context, val: LLVMValueRef) -> LLVMValueRef {
+    let value_clone = ctx.extern_functions.get("__value_clone").unwrap();
+    let args = [val];
+
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        value_clone.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"__value_clone\0".as_ptr() as *const _,
+    )
+}
+
+/// Turn the result of a native call into a value, native functions may return a null pointer.
+unsafe fn build_result(ctx: &mut Context, val: LLVMValueRef) -> LLVMValueRef {
+    let value_result = ctx.extern_functions.get("__value_result").unwrap();
+    let args = [val];
+
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        value_result.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"__value_result\0".as_ptr() as *const _,
+    )
+}
+
+/// Give back a value which is used up.
+unsafe fn build_delete(ctx: &mut Context, val: LLVMValueRef) {
+    let value_delete = ctx.extern_functions.get("__value_delete").unwrap();
+    let args = [val];
+
+    LLVMBuildCall(
+        ctx.llvm_builder,
+        value_delete.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"\0".as_ptr() as *const _,
+    );
+}
+
+/// Jump to `target`, see `build_dead_block`.
+unsafe fn build_jump(ctx: &mut Context, target: LLVMBasicBlockRef) {
+    LLVMBuildBr(ctx.llvm_builder, target);
+    build_dead_block(ctx);
+}
+
+/// Continue building in a fresh, unreachable block after a terminator,
+/// so statements following a jump or return still have a place to go.
+unsafe fn build_dead_block(ctx: &mut Context) {
+    let func = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctx.llvm_builder));
+    let dead_bb = LLVMAppendBasicBlockInContext(ctx.llvm_ctx, func, b"__dead\0".as_ptr() as *const _);
+
+    LLVMPositionBuilderAtEnd(ctx.llvm_builder, dead_bb);
+    *ctx.block_stack.last_mut().unwrap() = dead_bb;
+}
+
+/// Evaluate an expression and test it with `Value::is_truthy`, resulting in an `i1`.
+unsafe fn build_truthy(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
+    let val = exp(ctx, pair)?;
+
+    Ok(build_test(ctx, val))
+}
+
+/// Test the value `val`, which is used up, like `build_truthy`.
+unsafe fn build_test(ctx: &mut Context, val: LLVMValueRef) -> LLVMValueRef {
+    let truthy = ctx.extern_functions.get("__truthy").unwrap();
+    let args = [val];
+
+    let res = LLVMBuildCall(
+        ctx.llvm_builder,
+        truthy.0,
+        args.as_ptr() as *mut LLVMValueRef,
+        args.len() as u32,
+        b"__truthy\0".as_ptr() as *const _,
+    );
+
+    build_delete(ctx, val);
+
+    LLVMBuildICmp(
+        ctx.llvm_builder,
+        llvm::LLVMIntPredicate::LLVMIntNE,
+        res,
+        LLVMConstInt(LLVMInt8TypeInContext(ctx.llvm_ctx), 0, 0),
+        b"__cond\0".as_ptr() as *const _,
+    )
+}
+
+/// Fill `bb` with whatever `f` builds and jump to `next` afterwards,
+/// unless the branch already ended in a terminator.
+unsafe fn build_branch<F>(ctx: &mut Context, bb: LLVMBasicBlockRef, next: LLVMBasicBlockRef, f: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Context) -> Result<LLVMValueRef, Error>,
+{
+    LLVMPositionBuilderAtEnd(ctx.llvm_builder, bb);
+    *ctx.block_stack.last_mut().unwrap() = bb;
+
+    f(ctx)?;
+
+    let current = LLVMGetInsertBlock(ctx.llvm_builder);
+
+    if LLVMGetBasicBlockTerminator(current).is_null() {
+        LLVMBuildBr(ctx.llvm_builder, next);
     }
+
+    Ok(())
 }
 
-// fn _if(pair: Pair<Rule>) {
-//     let mut the_if = pair.into_inner();
-//
-//     let cond = exp(the_if.next().unwrap());
-//     let block = if let Ok(Ast::Block(b)) = Rc::try_unwrap(block(the_if.next().unwrap())) {
-//         b
-//     } else {
-//         Vec::new()
-//     };
-//     let elsy = match the_if.next() {
-//         Some(block) => _else(block),
-//         None => Rc::new(Ast::Nothing),
-//     };
-//
-//     Rc::new(Ast::If(cond, block, elsy))
-// }
-//
-// fn _else(pair: Pair<Rule>) {
-//     let mut the_else = pair.into_inner();
-//
-//     Rc::new(Ast::Nothing)
-// }
-
-fn dict(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+fn dict(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "dict >>");
     let inner = pair.into_inner();
-    let dict_new = ctx.extern_functions.get("__dict_new").unwrap().clone();
-    let dict_insert = ctx.extern_functions.get("__dict_insert").unwrap().clone();
+    let dict_new = ctx.extern_functions["__dict_new"];
+    let dict_insert = ctx.extern_functions["__dict_insert"];
 
     let dct = unsafe {
         LLVMBuildCall(
             ctx.llvm_builder,
             dict_new.0,
-            0 as *mut LLVMValueRef,
+            ptr::null_mut(),
             0,
             b"__dict_new\0".as_ptr() as *const _,
         )
@@ -637,7 +1397,8 @@ fn dict(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
 
         unsafe {
             let ident = string(ctx, inner.next().unwrap());
-            let args = vec![dct, ident, exp(ctx, inner.next().unwrap())];
+            let val = exp(ctx, inner.next().unwrap())?;
+            let args = [dct, ident, val];
 
             LLVMBuildCall(
                 ctx.llvm_builder,
@@ -646,25 +1407,28 @@ fn dict(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
                 args.len() as u32,
                 b"__dict_insert\0".as_ptr() as *const _,
             );
+
+            build_delete(ctx, ident);
+            build_delete(ctx, val);
         }
     }
 
     debug!(target: "runjit.build", "dict <<");
 
-    dct
+    Ok(dct)
 }
 
-fn array(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
+fn array(ctx: &mut Context, pair: Pair<Rule>) -> Result<LLVMValueRef, Error> {
     debug!(target: "runjit.build", "array >>");
     let inner = pair.into_inner();
-    let array_new = ctx.extern_functions.get("__array_new").unwrap().clone();
-    let array_push = ctx.extern_functions.get("__array_push").unwrap().clone();
+    let array_new = ctx.extern_functions["__array_new"];
+    let array_push = ctx.extern_functions["__array_push"];
 
     let arr = unsafe {
         LLVMBuildCall(
             ctx.llvm_builder,
             array_new.0,
-            0 as *mut LLVMValueRef,
+            ptr::null_mut(),
             0,
             b"__array_new\0".as_ptr() as *const _,
         )
@@ -672,7 +1436,8 @@ fn array(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
 
     for p in inner {
         unsafe {
-            let args = vec![arr, exp(ctx, p)];
+            let val = exp(ctx, p)?;
+            let args = [arr, val];
 
             LLVMBuildCall(
                 ctx.llvm_builder,
@@ -681,8 +1446,10 @@ fn array(ctx: &mut Context, pair: Pair<Rule>) -> LLVMValueRef {
                 args.len() as u32,
                 b"__array_push\0".as_ptr() as *const _,
             );
+
+            build_delete(ctx, val);
         }
     }
     debug!(target: "runjit.build", "array <<");
-    arr
+    Ok(arr)
 }
@@ -1,179 +1,464 @@
 //!
 //! Nice and fluffy rust callbacks for the llvm generated code.
 //!
+//! # Ownership
+//!
+//! Values are passed around as `*const Value` from `Rc::into_raw`, each pointer held by the
+//! generated code stands for one strong reference.
+//!
+//! * Callbacks borrow their arguments, they never take a reference away from the caller.
+//!   What a callback keeps, like an element pushed into an array, it clones.
+//! * A returned value is owned by the caller, who has to give it back with `value_delete`.
+//!   Callbacks which have no result, like `global_set`, return a null pointer instead.
+//! * `Null` is shared, see `null`, so handing out a `Null` does not allocate.
+//!
+//! The builder takes care of releasing every temporary value, see `jit::build`.
+//!
+use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
 use std::rc::Rc;
+use std::slice;
 
-use libc;
+use error::Error;
+use jit::{path, Context, Value};
 
-use jit::{Context, Value};
+thread_local! {
+    static NULL: Rc<Value> = Rc::new(Value::Null);
+}
 
-pub unsafe extern "C" fn global_get(ctx: *mut Context, name: *const Value) -> *const Value {
-    debug!(target: "runjit.callback", "!! get {:?} !!", *name);
+/// A new reference to the shared `Null`.
+pub fn null() -> *const Value {
+    NULL.with(|n| Rc::into_raw(n.clone()))
+}
 
-    if let Value::Array(ref a) = *name {
-        if let Value::Str(ref s) = *a[0] {
-            let val = (*ctx).runtime_variables.get(s);
+/// Take a new reference to a borrowed value.
+unsafe fn clone_raw(v: *const Value) -> Rc<Value> {
+    let rc = Rc::from_raw(v);
+    let cloned = rc.clone();
+    mem::forget(rc);
+    cloned
+}
 
-            if let Some(val) = val {
-                return Rc::into_raw(val.clone());
-            }
+/// Keep the first error of a run in the context, the generated code can not unwind.
+fn fail(ctx: &mut Context, err: Error) {
+    if ctx.runtime_error.is_none() {
+        ctx.runtime_error = Some(err);
+    }
+}
+
+/// The parts of an access path, see `jit::path`.
+unsafe fn parts(name: *const Value) -> Vec<Rc<Value>> {
+    match *name {
+        Value::Array(ref a) if !a.borrow().is_empty() => a.borrow().clone(),
+        _ => panic!("access path has to be a non empty array"),
+    }
+}
+
+/// Take a new reference to a borrowed value, a null pointer, like a local which is not
+/// assigned yet, is `Null`.
+unsafe fn borrowed(v: *const Value) -> Rc<Value> {
+    if v.is_null() {
+        NULL.with(|n| n.clone())
+    } else {
+        clone_raw(v)
+    }
+}
+
+fn not_defined(parts: &[Rc<Value>]) -> Error {
+    Error::Runtime(format!("`{}` is not defined", path::name(&parts[..1])))
+}
+
+/// The value at the path `name`, starting with a global variable.
+unsafe fn global(ctx: &Context, name: *const Value) -> Result<Rc<Value>, Error> {
+    let parts = parts(name);
+
+    let root = match *parts[0] {
+        Value::Str(ref s) => ctx.runtime_variables.get(s),
+        _ => None,
+    };
+
+    match root {
+        Some(root) => path::lookup(root, &parts),
+        None => Err(not_defined(&parts)),
+    }
+}
+
+/// Keep `res` as result, or the error in the context and `Null` as result.
+fn value_or_null(ctx: &mut Context, res: Result<Rc<Value>, Error>) -> *const Value {
+    match res {
+        Ok(val) => Rc::into_raw(val),
+        Err(e) => {
+            fail(ctx, e);
+            null()
         }
     }
+}
 
-    &Value::Null as *const _
+pub unsafe extern "C" fn global_get(ctx: *mut Context, name: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! get {:?} !!", *name);
+    let ctx = &mut *ctx;
+    let res = global(ctx, name);
+    value_or_null(ctx, res)
 }
 
-pub unsafe extern "C" fn global_get_func(ctx: *mut Context, name: *const Value) -> usize {
-    debug!(target: "runjit.callback", "!! get func {:?} !!", *name);
+/// Stands in for a lambda which could not be found, the error is already in the context.
+extern "C" fn not_callable() -> *const Value {
+    null()
+}
 
-    if let Value::Array(ref a) = *name {
-        if let Value::Str(ref s) = *a[0] {
-            let val = (*ctx).runtime_variables.get(s);
+/// The value at the path `name` inside of the local `root`, `name` starts with the
+/// name of the local, for error messages.
+pub unsafe extern "C" fn value_get(ctx: *mut Context, root: *const Value, name: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! value get {:?} !!", *name);
+    let ctx = &mut *ctx;
+    let res = path::lookup(&borrowed(root), &parts(name));
+    value_or_null(ctx, res)
+}
 
-            if let Value::Lambda(v) = **val.unwrap() {
-                return v;
-            }
+/// The address of the borrowed lambda `val`, found at `name`, for calling it with `argc` arguments.
+///
+/// The environment of a closure is stored in `env`, which is the first argument of the
+/// call, for any other lambda `env` is a null pointer. The caller keeps `val` alive until
+/// the call returns, so the environment stays valid.
+pub unsafe extern "C" fn callee(
+    ctx: *mut Context,
+    val: *const Value,
+    name: *const Value,
+    argc: usize,
+    env: *mut *const Vec<Rc<Value>>,
+) -> usize {
+    debug!(target: "runjit.callback", "!! callee {:?} !!", *name);
+    let ctx = &mut *ctx;
+    *env = ptr::null();
+
+    let (addr, captured) = match *val {
+        Value::Lambda(addr) => (addr, ptr::null()),
+        Value::Closure(addr, ref captured) => (addr, &**captured as *const _),
+        ref other => {
+            let err = Error::Runtime(format!(
+                "can not call `{}`, it is {}",
+                path::name(&parts(name)),
+                other.type_name()
+            ));
+            fail(ctx, err);
+            return not_callable as extern "C" fn() -> *const Value as usize;
+        }
+    };
+
+    match ctx.functions.get(&addr) {
+        Some(&arity) if arity != argc => {
+            let err = Error::Runtime(format!(
+                "`{}` takes {} arguments, {} given",
+                path::name(&parts(name)),
+                arity,
+                argc
+            ));
+            fail(ctx, err);
+            not_callable as extern "C" fn() -> *const Value as usize
+        }
+        Some(_) => {
+            *env = captured;
+            addr
+        }
+        None => {
+            let err = Error::Runtime(format!(
+                "can not call `{}`, no lambda at {:#x}",
+                path::name(&parts(name)),
+                addr
+            ));
+            fail(ctx, err);
+            not_callable as extern "C" fn() -> *const Value as usize
         }
     }
+}
 
-    0
+/// Assign `val` at the path `name` inside of the local `root`, see `value_get`.
+pub unsafe extern "C" fn value_set(
+    ctx: *mut Context,
+    root: *const Value,
+    name: *const Value,
+    val: *const Value,
+) -> *const Value {
+    debug!(target: "runjit.callback", "!! value set {:?} = {:?} !!", *name, *val);
+    let ctx = &mut *ctx;
+
+    if let Err(e) = path::assign(&borrowed(root), &parts(name), clone_raw(val)) {
+        fail(ctx, e);
+    }
+
+    ptr::null()
 }
 
 pub unsafe extern "C" fn global_set(
     ctx: *mut Context,
     name: *const Value,
-    val: *mut Value,
+    val: *const Value,
 ) -> *const Value {
     debug!(target: "runjit.callback", "!! set {:?} = {:?} !!", *name, *val);
+    let ctx = &mut *ctx;
+    let parts = parts(name);
 
-    if let Value::Array(ref a) = *name {
-        if let Value::Str(ref s) = *a[0] {
-            (*ctx).runtime_variables.insert(
-                s.clone(),
-                Rc::from_raw(val),
-            );
-        }
+    let key = match *parts[0] {
+        Value::Str(ref s) => s,
+        _ => panic!("access path has to start with a name"),
+    };
+
+    if parts.len() == 1 {
+        ctx.runtime_variables.insert(key.clone(), clone_raw(val));
+        return ptr::null();
     }
 
-    &Value::Null as *const _
-}
+    let res = match ctx.runtime_variables.get(key) {
+        Some(root) => path::assign(root, &parts, clone_raw(val)),
+        None => Err(not_defined(&parts)),
+    };
 
-pub unsafe extern "C" fn add(left: *const Value, right: *const Value) -> *const Value {
-    debug!(target: "runjit.callback", "!! add !!");
+    if let Err(e) = res {
+        fail(ctx, e);
+    }
 
-    let left_rc = Rc::from_raw(left);
-    let right_rc = Rc::from_raw(right);
+    ptr::null()
+}
 
-    if let Value::Float(l) = *left_rc {
-        if let Value::Float(r) = *right_rc {
-            return Rc::into_raw(Rc::new(Value::Float(l + r)));
+/// Apply an arithmetic operator, `sym` is only used in errors.
+///
+/// Two ints result in an int, an int and a float are promoted to float. Overflow, division
+/// by zero and operands which are not numbers are errors resulting in `Null`.
+unsafe fn arith(
+    ctx: *mut Context,
+    left: *const Value,
+    right: *const Value,
+    sym: &str,
+    int: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> *const Value {
+    let res = match (&*left, &*right) {
+        (&Value::Int(l), &Value::Int(r)) => match int(l, r) {
+            Some(i) => Value::Int(i),
+            None => {
+                let what = if r == 0 && (sym == "/" || sym == "%") {
+                    "division by zero"
+                } else {
+                    "integer overflow"
+                };
+                fail(&mut *ctx, Error::Runtime(format!("{} in `{} {} {}`", what, l, sym, r)));
+                return null();
+            }
+        },
+        (&Value::Int(l), &Value::Float(r)) => Value::Float(float(l as f64, r)),
+        (&Value::Float(l), &Value::Int(r)) => Value::Float(float(l, r as f64)),
+        (&Value::Float(l), &Value::Float(r)) => Value::Float(float(l, r)),
+        (l, r) => {
+            let err = Error::Runtime(format!(
+                "can not apply `{}` to {} and {}",
+                sym,
+                l.type_name(),
+                r.type_name()
+            ));
+            fail(&mut *ctx, err);
+            return null();
         }
-    }
+    };
 
-    Rc::into_raw(Rc::new(Value::Float(0.0)))
+    Rc::into_raw(Rc::new(res))
 }
 
-pub unsafe extern "C" fn sub(left: *const Value, right: *const Value) -> *const Value {
+pub unsafe extern "C" fn add(ctx: *mut Context, left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! add !!");
+    arith(ctx, left, right, "+", i64::checked_add, |l, r| l + r)
+}
+
+pub unsafe extern "C" fn sub(ctx: *mut Context, left: *const Value, right: *const Value) -> *const Value {
     debug!(target: "runjit.callback", "!! sub !!");
+    arith(ctx, left, right, "-", i64::checked_sub, |l, r| l - r)
+}
+
+pub unsafe extern "C" fn mul(ctx: *mut Context, left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! mul !!");
+    arith(ctx, left, right, "*", i64::checked_mul, |l, r| l * r)
+}
+
+/// Division of two ints truncates towards zero, like rust does.
+pub unsafe extern "C" fn div(ctx: *mut Context, left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! div !!");
+    arith(ctx, left, right, "/", i64::checked_div, |l, r| l / r)
+}
 
-    let left_rc = Rc::from_raw(left);
-    let right_rc = Rc::from_raw(right);
+/// Remainder with the sign of `left`.
+pub unsafe extern "C" fn modulo(ctx: *mut Context, left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! mod !!");
+    arith(ctx, left, right, "%", i64::checked_rem, |l, r| l % r)
+}
 
-    if let Value::Float(l) = *left_rc {
-        if let Value::Float(r) = *right_rc {
-            return Rc::into_raw(Rc::new(Value::Float(l - r)));
+/// Unary `-`, negating `i64::min_value()` is an integer overflow. Anything but a number
+/// is an error resulting in `Null`.
+pub unsafe extern "C" fn neg(ctx: *mut Context, v: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! neg !!");
+
+    let res = match *v {
+        Value::Int(i) => match i.checked_neg() {
+            Some(i) => Value::Int(i),
+            None => {
+                fail(&mut *ctx, Error::Runtime(format!("integer overflow in `-({})`", i)));
+                return null();
+            }
+        },
+        Value::Float(f) => Value::Float(-f),
+        ref other => {
+            fail(&mut *ctx, unary_mismatch("-", other));
+            return null();
         }
-    }
+    };
 
-    Rc::into_raw(Rc::new(Value::Float(0.0)))
+    Rc::into_raw(Rc::new(res))
 }
 
-pub unsafe extern "C" fn mul(left: *const Value, right: *const Value) -> *const Value {
-    debug!(target: "runjit.callback", "!! mul !!");
-
-    let left_rc = Rc::from_raw(left);
-    let right_rc = Rc::from_raw(right);
+/// Unary `+`, a number stays as it is. Anything else is an error resulting in `Null`.
+pub unsafe extern "C" fn pos(ctx: *mut Context, v: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! pos !!");
 
-    if let Value::Float(l) = *left_rc {
-        if let Value::Float(r) = *right_rc {
-            return Rc::into_raw(Rc::new(Value::Float(l * r)));
+    match *v {
+        Value::Int(_) | Value::Float(_) => Rc::into_raw(clone_raw(v)),
+        ref other => {
+            fail(&mut *ctx, unary_mismatch("+", other));
+            null()
         }
     }
+}
 
-    Rc::into_raw(Rc::new(Value::Float(0.0)))
+/// Unary `!`, true if the value is not truthy, see `Value::is_truthy`.
+pub unsafe extern "C" fn not(v: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! not !!");
+    boolean(!(*v).is_truthy())
 }
 
-pub unsafe extern "C" fn div(left: *const Value, right: *const Value) -> *const Value {
-    debug!(target: "runjit.callback", "!! div !!");
+fn unary_mismatch(sym: &str, val: &Value) -> Error {
+    Error::Runtime(format!("can not apply unary `{}` to {}", sym, val.type_name()))
+}
+
+/// The script representation of a logical result, a `Bool`.
+fn boolean(b: bool) -> *const Value {
+    Rc::into_raw(Rc::new(Value::Bool(b)))
+}
 
-    let left_rc = Rc::from_raw(left);
-    let right_rc = Rc::from_raw(right);
+/// Values of the same type are compared by content, arrays and dicts element by element,
+/// lambdas by address. Values of different types are never equal, except an int and a float
+/// with the same numeric value, also inside of arrays and dicts.
+pub unsafe extern "C" fn eq(left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! eq !!");
+    boolean(equal(&*left, &*right))
+}
+
+pub unsafe extern "C" fn neq(left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! neq !!");
+    boolean(!equal(&*left, &*right))
+}
 
-    if let Value::Float(l) = *left_rc {
-        if let Value::Float(r) = *right_rc {
-            return Rc::into_raw(Rc::new(Value::Float(l / r)));
+pub(crate) fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (&Value::Int(i), &Value::Float(f)) | (&Value::Float(f), &Value::Int(i)) => i as f64 == f,
+        (Value::Array(l), Value::Array(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| equal(l, r))
+        }
+        (Value::Dict(l), Value::Dict(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|((lk, l), (rk, r))| lk == rk && equal(l, r))
         }
+        _ => left == right,
     }
+}
+
+/// Numbers, strings and arrays are ordered, see the `PartialOrd` of `Value`.
+/// Any other comparison is false, except `>=` and `<=` for equal values.
+pub unsafe extern "C" fn gt(left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! gt !!");
+    boolean(*left > *right)
+}
 
-    Rc::into_raw(Rc::new(Value::Float(0.0)))
+pub unsafe extern "C" fn le(left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! le !!");
+    boolean(*left < *right)
+}
+
+pub unsafe extern "C" fn gte(left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! gte !!");
+    boolean(*left >= *right)
+}
+
+pub unsafe extern "C" fn lee(left: *const Value, right: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! lee !!");
+    boolean(*left <= *right)
 }
 
 pub extern "C" fn array_new() -> *const Value {
     debug!(target: "runjit.callback", "!! new array !!");
-    Rc::into_raw(Rc::new(Value::Array(Vec::new())))
+    Rc::into_raw(Rc::new(Value::Array(Rc::new(RefCell::new(Vec::new())))))
 }
 
-pub unsafe extern "C" fn array_push(arr: *mut Value, v: *mut Value) -> *const Value {
+pub unsafe extern "C" fn array_push(arr: *const Value, v: *const Value) -> *const Value {
     debug!(target: "runjit.callback", "!! pushing value !! {:?} {:?}", *arr, *v);
 
-    if let Value::Array(ref mut a) = *arr {
-        a.push(Rc::from_raw(v));
+    if let Value::Array(ref a) = *arr {
+        a.borrow_mut().push(clone_raw(v));
     }
 
-    &Value::Null as *const _
+    ptr::null()
+}
+
+/// The `push` method of an array, appends `v` to the array in `env`, see `path::child`.
+pub unsafe extern "C" fn array_append(env: *const Vec<Rc<Value>>, v: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! append value !! {:?}", *v);
+
+    if let Value::Array(ref a) = *(&*env)[0] {
+        a.borrow_mut().push(clone_raw(v));
+    }
+
+    null()
 }
 
 pub extern "C" fn dict_new() -> *const Value {
     debug!(target: "runjit.callback", "!! new dict !!");
 
-    Rc::into_raw(Rc::new(Value::Dict(BTreeMap::new())))
+    Rc::into_raw(Rc::new(Value::Dict(Rc::new(RefCell::new(BTreeMap::new())))))
 }
 
 pub unsafe extern "C" fn dict_insert(
-    dct: *mut Value,
-    key: *mut Value,
-    val: *mut Value,
+    dct: *const Value,
+    key: *const Value,
+    val: *const Value,
 ) -> *const Value {
-    if let Value::Dict(ref mut d) = *dct {
+    if let Value::Dict(ref d) = *dct {
         if let Value::Str(ref k) = *key {
-            d.insert(k.clone(), Rc::from_raw(val));
+            d.borrow_mut().insert(k.clone(), clone_raw(val));
         }
     }
 
-    &Value::Null as *const _
+    ptr::null()
 }
 
-pub unsafe extern "C" fn dict_remove(dct: *mut Value, key: *mut Value) -> *const Value {
-    if let Value::Dict(ref mut d) = *dct {
+pub unsafe extern "C" fn dict_remove(dct: *const Value, key: *const Value) -> *const Value {
+    if let Value::Dict(ref d) = *dct {
         if let Value::Str(ref k) = *key {
-            d.remove(k);
+            d.borrow_mut().remove(k);
         }
     }
-    &Value::Null as *const _
+
+    ptr::null()
 }
 
 pub extern "C" fn string_new() -> *const Value {
     debug!(target: "runjit.callback", "!! new string !!");
-    Rc::into_raw(Rc::new(Value::Str(CString::new("").unwrap())))
+    Rc::into_raw(Rc::new(Value::Str(String::new())))
 }
 
-pub unsafe extern "C" fn string_from(bytes: *mut libc::c_char) -> *const Value {
+/// A `Str` of the `len` bytes at `bytes`, which may contain NUL bytes.
+pub unsafe extern "C" fn string_from(bytes: *const u8, len: usize) -> *const Value {
     debug!(target: "runjit.callback", "!! string from !!");
-    // let data = CString::from_raw(bytes);
-    let data = CStr::from_ptr(bytes);
-    Rc::into_raw(Rc::new(Value::Str(data.to_owned())))
+    let data = slice::from_raw_parts(bytes, len);
+    Rc::into_raw(Rc::new(Value::Str(String::from_utf8_lossy(data).into_owned())))
 }
 
 pub extern "C" fn float_new(v: f64) -> *const Value {
@@ -181,15 +466,105 @@ pub extern "C" fn float_new(v: f64) -> *const Value {
     Rc::into_raw(Rc::new(Value::Float(v)))
 }
 
-pub extern "C" fn lambda_new(v: usize) -> *const Value {
-    debug!(target: "runjit.callback", "!! new lambda {} !!", v);
-    Rc::into_raw(Rc::new(Value::Lambda(v)))
+pub extern "C" fn int_new(v: i64) -> *const Value {
+    debug!(target: "runjit.callback", "!! new int {} !!", v);
+    Rc::into_raw(Rc::new(Value::Int(v)))
+}
+
+pub extern "C" fn null_new() -> *const Value {
+    debug!(target: "runjit.callback", "!! new null !!");
+    null()
+}
+
+/// A `Bool`, `b` is the zero extended `i1` of the generated code.
+pub extern "C" fn bool_new(b: u8) -> *const Value {
+    debug!(target: "runjit.callback", "!! new bool {} !!", b);
+    boolean(b != 0)
+}
+
+pub unsafe extern "C" fn truthy(v: *const Value) -> u8 {
+    debug!(target: "runjit.callback", "!! truthy {:?} !!", *v);
+    (*v).is_truthy() as u8
 }
 
-pub extern "C" fn value_delete(a: *const Value) -> *const Value {
+/// A closure of the lambda at `addr`, capturing the values of the array `env`.
+pub unsafe extern "C" fn closure_new(addr: usize, env: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! new closure {} !!", addr);
+
+    let captured = match *env {
+        Value::Array(ref a) => a.borrow().clone(),
+        _ => panic!("environment has to be an array"),
+    };
+
+    Rc::into_raw(Rc::new(Value::Closure(addr, Rc::new(captured))))
+}
+
+/// A new reference to the captured value `index` of the environment of a closure.
+pub unsafe extern "C" fn env_get(env: *const Vec<Rc<Value>>, index: usize) -> *const Value {
+    debug!(target: "runjit.callback", "!! env get {} !!", index);
+    Rc::into_raw((&*env)[index].clone())
+}
+
+/// Call the host function `id` with `argc` borrowed arguments, see `Context::register`.
+///
+/// Errors are kept in the context and result in `Null`, the generated code can not unwind.
+pub unsafe extern "C" fn host_call(
+    ctx: *mut Context,
+    id: usize,
+    argc: usize,
+    argv: *const *const Value,
+) -> *const Value {
+    let ctx = &mut *ctx;
+
+    let res = {
+        let host = &ctx.host_functions[id];
+        debug!(target: "runjit.callback", "!! host call {} !!", host.name);
+
+        match host.arity {
+            Some(arity) if arity != argc => Err(Error::Call(format!(
+                "`{}` takes {} arguments, {} given",
+                host.name,
+                arity,
+                argc
+            ))),
+            _ => {
+                let args: Vec<&Value> = slice::from_raw_parts(argv, argc).iter().map(|a| &**a).collect();
+                (host.f)(&args)
+            }
+        }
+    };
+
+    match res {
+        Ok(val) => Rc::into_raw(Rc::new(val)),
+        Err(e) => {
+            fail(ctx, e);
+            null()
+        }
+    }
+}
+
+/// Take a new reference to `a`, see `borrowed`.
+pub unsafe extern "C" fn value_clone(a: *const Value) -> *const Value {
+    debug!(target: "runjit.callback", "!! clone value !!");
+    Rc::into_raw(borrowed(a))
+}
+
+/// Keep the result of a call, a null pointer becomes the shared `Null`.
+pub unsafe extern "C" fn value_result(a: *const Value) -> *const Value {
+    if a.is_null() {
+        null()
+    } else {
+        a
+    }
+}
+
+/// Give back a reference, a null pointer is ignored.
+pub unsafe extern "C" fn value_delete(a: *const Value) -> *const Value {
     debug!(target: "runjit.callback", "!! delete value !!");
 
-    unsafe { Rc::from_raw(a) };
+    if !a.is_null() {
+        Rc::from_raw(a);
+    }
 
-    &Value::Null as *const _
+    ptr::null()
 }
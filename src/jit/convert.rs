@@ -0,0 +1,447 @@
+//!
+//! Conversions between script values and rust types, used by host functions and `Context::call`.
+//!
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use error::Error;
+use jit::Value;
+use types;
+
+/// Rust types which can be created from a script value.
+pub trait FromValue: Sized {
+    fn from_value(val: &Value) -> Result<Self, Error>;
+}
+
+/// Rust types which can be turned into a script value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub(crate) fn mismatch(expected: &str, found: &Value) -> Error {
+    Error::Type(format!("expected {}, got {}", expected, found.type_name()))
+}
+
+/// Add where in a collection a conversion failed to its error.
+fn within(err: Error, place: &str) -> Error {
+    match err {
+        Error::Type(msg) => Error::Type(format!("{} at {}", msg, place)),
+        other => other,
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(val: &Value) -> Result<Value, Error> {
+        Ok(val.clone())
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(val: &Value) -> Result<f64, Error> {
+        match *val {
+            Value::Float(f) => Ok(f),
+            Value::Int(i) => Ok(i as f64),
+            _ => Err(mismatch("float", val)),
+        }
+    }
+}
+
+macro_rules! int_from_value {
+    ($($int:ty),*) => {$(
+        impl FromValue for $int {
+            fn from_value(val: &Value) -> Result<$int, Error> {
+                match *val {
+                    Value::Int(i) => <$int>::try_from(i).map_err(|_| Error::Type(format!(
+                        "expected {}, got int {} which does not fit",
+                        stringify!($int),
+                        i
+                    ))),
+                    // `MAX + 1` is a power of two, so it is exact, while `MAX` of the 64 bit
+                    // types rounds up to it
+                    Value::Float(f) if f.fract() == 0.0 && f >= <$int>::MIN as f64 && f < <$int>::MAX as f64 + 1.0 => {
+                        Ok(f as $int)
+                    }
+                    Value::Float(f) => Err(Error::Type(format!(
+                        "expected {}, got float {} which does not fit",
+                        stringify!($int),
+                        f
+                    ))),
+                    _ => Err(mismatch(stringify!($int), val)),
+                }
+            }
+        }
+
+        /// An `Int`, or a `Float` if it does not fit into an `i64`.
+        impl IntoValue for $int {
+            fn into_value(self) -> Value {
+                i64::try_from(self).map(Value::Int).unwrap_or(Value::Float(self as f64))
+            }
+        }
+    )*};
+}
+
+int_from_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromValue for bool {
+    fn from_value(val: &Value) -> Result<bool, Error> {
+        match *val {
+            Value::Bool(b) => Ok(b),
+            _ => Err(mismatch("bool", val)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(val: &Value) -> Result<String, Error> {
+        str_of(val).map(String::from)
+    }
+}
+
+/// `Null` is `None`, everything else is converted to `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(val: &Value) -> Result<Option<T>, Error> {
+        match *val {
+            Value::Null => Ok(None),
+            _ => T::from_value(val).map(Some),
+        }
+    }
+}
+
+impl FromValue for () {
+    fn from_value(val: &Value) -> Result<(), Error> {
+        match *val {
+            Value::Null => Ok(()),
+            _ => Err(mismatch("null", val)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(val: &Value) -> Result<Vec<T>, Error> {
+        match *val {
+            Value::Array(ref a) => a
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(i, v)| T::from_value(v).map_err(|e| within(e, &format!("[{}]", i))))
+                .collect(),
+            _ => Err(mismatch("array", val)),
+        }
+    }
+}
+
+/// The entries of a dict, converted with `f`.
+fn entries<T, M, F>(val: &Value, f: F) -> Result<M, Error>
+where
+    M: ::std::iter::FromIterator<(String, T)>,
+    F: Fn(&Value) -> Result<T, Error>,
+{
+    match *val {
+        Value::Dict(ref d) => d
+            .borrow()
+            .iter()
+            .map(|(k, v)| {
+                let v = f(v).map_err(|e| within(e, &format!("key `{}`", k)))?;
+                Ok((k.clone(), v))
+            })
+            .collect(),
+        _ => Err(mismatch("dict", val)),
+    }
+}
+
+impl<T: FromValue> FromValue for BTreeMap<String, T> {
+    fn from_value(val: &Value) -> Result<BTreeMap<String, T>, Error> {
+        entries(val, T::from_value)
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(val: &Value) -> Result<HashMap<String, T>, Error> {
+        entries(val, T::from_value)
+    }
+}
+
+fn str_of(val: &Value) -> Result<&str, Error> {
+    match *val {
+        Value::Str(ref s) => Ok(s),
+        _ => Err(mismatch("string", val)),
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::string(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::string(self)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Null
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::array(self.into_iter().map(IntoValue::into_value))
+    }
+}
+
+fn dict<K, T, I>(entries: I) -> Value
+where
+    K: Into<String>,
+    T: IntoValue,
+    I: IntoIterator<Item = (K, T)>,
+{
+    Value::dict(entries.into_iter().map(|(k, v)| (k, v.into_value())))
+}
+
+impl<T: IntoValue> IntoValue for BTreeMap<String, T> {
+    fn into_value(self) -> Value {
+        dict(self)
+    }
+}
+
+impl<T: IntoValue, S> IntoValue for HashMap<String, T, S>
+where
+    String: Eq + Hash,
+{
+    fn into_value(self) -> Value {
+        dict(self)
+    }
+}
+
+/// Arguments for `Context::call`, a slice of values or a tuple of `IntoValue`s.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for &[Value] {
+    fn into_args(self) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl IntoArgs for &Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self.clone()
+    }
+}
+
+impl<const N: usize> IntoArgs for &[Value; N] {
+    fn into_args(self) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl<T: IntoValue> IntoArgs for Vec<T> {
+    fn into_args(self) -> Vec<Value> {
+        self.into_iter().map(IntoValue::into_value).collect()
+    }
+}
+
+macro_rules! impl_into_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_value()),*]
+            }
+        }
+    };
+}
+
+impl_into_args!();
+impl_into_args!(A);
+impl_into_args!(A, B);
+impl_into_args!(A, B, C);
+impl_into_args!(A, B, C, D);
+impl_into_args!(A, B, C, D, E);
+impl_into_args!(A, B, C, D, E, F);
+
+/// Convert a script value for a `types::RustCall`, only numbers, bools, strings and `Null` have a
+/// counterpart in the executor.
+pub fn to_executor(val: &Value) -> Result<types::Value, Error> {
+    match *val {
+        Value::Int(i) => Ok(types::Value::Int(i)),
+        Value::Float(f) => Ok(types::Value::Float(f)),
+        Value::Bool(b) => Ok(types::Value::Bool(b)),
+        Value::Str(ref s) => Ok(types::Value::String(s.clone())),
+        Value::Null => Ok(types::Value::Null),
+        _ => Err(mismatch("number, bool, string or null", val)),
+    }
+}
+
+/// Convert the result of a `types::RustCall` back, see `to_executor`.
+pub fn from_executor(val: &types::Value) -> Result<Value, Error> {
+    match *val {
+        types::Value::Int(i) => Ok(Value::Int(i)),
+        types::Value::Float(f) => Ok(Value::Float(f)),
+        types::Value::Bool(b) => Ok(Value::Bool(b)),
+        types::Value::String(ref s) => Ok(Value::string(s.as_str())),
+        types::Value::Null => Ok(Value::Null),
+        _ => Err(Error::Type(String::from("expected number, bool, string or null from rust call"))),
+    }
+}
+
+/// A parameter type of a host function, see `Context::register`.
+///
+/// Implemented for every `FromValue` type, and for `&str` and `&Value`
+/// which borrow the argument for the duration of the call.
+pub trait Param {
+    #[doc(hidden)]
+    type Fetch: for<'a> Fetch<'a>;
+}
+
+/// Gets a parameter out of an argument, with the lifetime of the argument.
+#[doc(hidden)]
+pub trait Fetch<'a> {
+    type Item;
+
+    fn fetch(val: &'a Value) -> Result<Self::Item, Error>;
+}
+
+#[doc(hidden)]
+pub struct Owned<T>(PhantomData<T>);
+
+#[doc(hidden)]
+pub struct BorrowedStr;
+
+#[doc(hidden)]
+pub struct BorrowedValue;
+
+impl<T: FromValue> Param for T {
+    type Fetch = Owned<T>;
+}
+
+impl Param for &str {
+    type Fetch = BorrowedStr;
+}
+
+impl Param for &Value {
+    type Fetch = BorrowedValue;
+}
+
+impl<'a, T: FromValue> Fetch<'a> for Owned<T> {
+    type Item = T;
+
+    fn fetch(val: &'a Value) -> Result<T, Error> {
+        T::from_value(val)
+    }
+}
+
+impl<'a> Fetch<'a> for BorrowedStr {
+    type Item = &'a str;
+
+    fn fetch(val: &'a Value) -> Result<&'a str, Error> {
+        str_of(val)
+    }
+}
+
+impl<'a> Fetch<'a> for BorrowedValue {
+    type Item = &'a Value;
+
+    fn fetch(val: &'a Value) -> Result<&'a Value, Error> {
+        Ok(val)
+    }
+}
+
+/// The result of a host function.
+pub trait HostResult {
+    fn into_result(self) -> Result<Value, Error>;
+}
+
+impl<T: IntoValue> HostResult for Result<T, Error> {
+    fn into_result(self) -> Result<Value, Error> {
+        self.map(IntoValue::into_value)
+    }
+}
+
+impl<T: IntoValue> HostResult for T {
+    fn into_result(self) -> Result<Value, Error> {
+        Ok(self.into_value())
+    }
+}
+
+/// Rust functions and closures which can be registered with `Context::register`.
+///
+/// Implemented for everything callable with up to six `Param`s, returning a `HostResult`.
+pub trait HostFn<Args> {
+    /// The number of parameters.
+    fn arity(&self) -> usize;
+
+    /// Convert `args` and call the function, `args` has to have `arity` elements.
+    fn call(&self, args: &[&Value]) -> Result<Value, Error>;
+}
+
+macro_rules! impl_host_fn {
+    ($($param:ident),*) => {
+        impl<Func, R, $($param: Param),*> HostFn<($($param,)*)> for Func
+        where
+            Func: Fn($($param),*) -> R,
+            Func: for<'a> Fn($(<<$param as Param>::Fetch as Fetch<'a>>::Item),*) -> R,
+            R: HostResult,
+        {
+            fn arity(&self) -> usize {
+                let params: &[&str] = &[$(stringify!($param)),*];
+                params.len()
+            }
+
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call(&self, args: &[&Value]) -> Result<Value, Error> {
+                // pins the call to the higher ranked signature
+                fn call_inner<R, $($param),*>(f: impl Fn($($param),*) -> R, $($param: $param),*) -> R {
+                    f($($param),*)
+                }
+
+                let mut args = args.iter();
+                $(let $param = <<$param as Param>::Fetch as Fetch>::fetch(args.next().unwrap())?;)*
+
+                call_inner(self, $($param),*).into_result()
+            }
+        }
+    };
+}
+
+impl_host_fn!();
+impl_host_fn!(A);
+impl_host_fn!(A, B);
+impl_host_fn!(A, B, C);
+impl_host_fn!(A, B, C, D);
+impl_host_fn!(A, B, C, D, E);
+impl_host_fn!(A, B, C, D, E, F);
@@ -6,32 +6,122 @@ use llvm::prelude::*;
 use llvm::execution_engine::*;
 use llvm::target::*;
 
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::ffi::{CStr, CString};
-use std::io::{Write, Read};
+use std::io::Read;
 use std::mem;
 use std::rc::Rc;
 use std::ptr;
 
 use libc;
+use log::Level;
 
+use error::Error;
 use parser::*;
+use types;
 
 mod callbacks;
 mod build;
+mod convert;
+mod path;
+#[cfg(feature = "serde")]
+mod serial;
 use jit::callbacks::*;
-
-#[derive(Debug)]
+pub use jit::convert::*;
+#[cfg(feature = "serde")]
+pub use jit::serial::{from_value, to_value};
+
+/// A script value.
+///
+/// Arrays and dicts are shared, cloning one gives another handle to the same collection,
+/// like assigning it to another variable in a script does.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    Array(Vec<Rc<Value>>),
-    Dict(BTreeMap<CString, Rc<Value>>),
+    Array(Rc<RefCell<Vec<Rc<Value>>>>),
+    Dict(Rc<RefCell<BTreeMap<String, Rc<Value>>>>),
+    /// A function of the host, see `Context::register` and `Context::add_fn`.
     Lambda(usize),
+    /// A lambda of a script, with the variables it captured from enclosing lambdas.
+    Closure(usize, Rc<Vec<Rc<Value>>>),
+    Bool(bool),
+    Int(i64),
     Float(f64),
-    Str(CString),
+    Str(String),
     Null,
 }
 
+impl Value {
+    /// A `Str`.
+    pub fn string<S: Into<String>>(s: S) -> Value {
+        Value::Str(s.into())
+    }
+
+    /// An `Array` of `values`.
+    pub fn array<I: IntoIterator<Item = Value>>(values: I) -> Value {
+        Value::Array(Rc::new(RefCell::new(values.into_iter().map(Rc::new).collect())))
+    }
+
+    /// A `Dict` of `(key, value)` pairs.
+    pub fn dict<K, I>(entries: I) -> Value
+    where
+        K: Into<String>,
+        I: IntoIterator<Item = (K, Value)>,
+    {
+        Value::Dict(Rc::new(RefCell::new(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.into(), Rc::new(v)))
+                .collect(),
+        )))
+    }
+
+    /// The name of the type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Array(_) => "array",
+            Value::Dict(_) => "dict",
+            Value::Lambda(_) | Value::Closure(..) => "lambda",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Null => "null",
+        }
+    }
+
+    /// `Null`, `false`, `0` and `NaN` are false, everything else is true,
+    /// used by `if`, `loop`, `&&` and `||`.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Bool(b) => b,
+            Value::Int(i) => i != 0,
+            Value::Float(f) => f != 0.0 && !f.is_nan(),
+            _ => true,
+        }
+    }
+}
+
+/// Numbers, strings and arrays (element by element) have an order, an int compared
+/// with a float is promoted. Other values are only comparable if they are equal.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(l), Value::Int(r)) => l.partial_cmp(r),
+            (Value::Int(l), Value::Float(r)) => (*l as f64).partial_cmp(r),
+            (Value::Float(l), Value::Int(r)) => l.partial_cmp(&(*r as f64)),
+            (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+            (Value::Str(l), Value::Str(r)) => l.partial_cmp(r),
+            (Value::Array(l), Value::Array(r)) => l.partial_cmp(r),
+            _ if callbacks::equal(self, other) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
 impl Drop for Value {
     fn drop(&mut self) {
         debug!(target: "runjit.value", "droped value: {:?}", self);
@@ -46,10 +136,65 @@ pub struct Context {
     self_ptr: *const Context,
     llvm_builder: LLVMBuilderRef,
     llvm_module: LLVMModuleRef,
+    file: String,
     block_stack: Vec<LLVMBasicBlockRef>,
+    loop_stack: Vec<(LLVMBasicBlockRef, LLVMBasicBlockRef)>,
+    return_stack: Vec<(LLVMBasicBlockRef, LLVMValueRef)>,
     local_stack: Vec<BTreeMap<String, LLVMValueRef>>,
+    /// The names every lambda in `local_stack` captured, in the order of its environment.
+    capture_stack: Vec<Vec<String>>,
     extern_functions: BTreeMap<String, (LLVMValueRef, *mut libc::c_void)>,
-    runtime_variables: BTreeMap<CString, Rc<Value>>,
+    runtime_variables: BTreeMap<String, Rc<Value>>,
+    /// One engine per `run` with changes, each with its own copy of the module,
+    /// older ones are kept alive for the lambdas they hand out.
+    engines: Vec<LLVMExecutionEngineRef>,
+    /// True if the module changed since the last engine was created.
+    changed: bool,
+    /// The main functions of all compiled scripts, in order.
+    mains: Vec<CString>,
+    /// The number of `mains` that have been executed by `run`.
+    ran: usize,
+    /// The number of parameters of every callable address, known after `run`.
+    functions: BTreeMap<usize, usize>,
+    host_functions: Vec<HostFunction>,
+    /// The first error of a host function during `run` or `call`.
+    runtime_error: Option<Error>,
+}
+
+/// A function added with `Context::register`, called through `callbacks::host_call`.
+struct HostFunction {
+    name: String,
+    /// `None` for functions taking any number of arguments.
+    arity: Option<usize>,
+    f: HostClosure,
+}
+
+type HostClosure = Box<dyn Fn(&[&Value]) -> Result<Value, Error>>;
+
+/// Lambdas with more parameters can not be called by `Context::call`.
+const MAX_CALL_ARGS: usize = 6;
+
+/// What `Context::call` can invoke, the name of a global variable or a lambda value.
+pub trait Callee {
+    fn lambda(&self, ctx: &Context) -> Result<Rc<Value>, Error>;
+}
+
+impl Callee for &str {
+    fn lambda(&self, ctx: &Context) -> Result<Rc<Value>, Error> {
+        match ctx.get(self) {
+            Some(val) => (&*val).lambda(ctx),
+            None => Err(Error::Call(format!("`{}` is not defined", self))),
+        }
+    }
+}
+
+impl Callee for &Value {
+    fn lambda(&self, _: &Context) -> Result<Rc<Value>, Error> {
+        match **self {
+            Value::Lambda(_) | Value::Closure(..) => Ok(Rc::new((*self).clone())),
+            ref other => Err(Error::Call(format!("{:?} is not a lambda", other))),
+        }
+    }
 }
 
 impl Context {
@@ -63,17 +208,28 @@ impl Context {
                 llvm_ctx: context,
                 llvm_f64: LLVMDoubleTypeInContext(context),
                 llvm_ptr: LLVMPointerType(LLVMInt64TypeInContext(context), 0),// TODO adept pointer size to actual maschine size
-                llvm_ctx_ptr: 0 as *mut _,
-                self_ptr: 0 as *const Context,
+                llvm_ctx_ptr: ptr::null_mut(),
+                self_ptr: ptr::null(),
                 llvm_builder: LLVMCreateBuilderInContext(context),
                 llvm_module: LLVMModuleCreateWithNameInContext(
                     b"__main__\0".as_ptr() as *const _,
                     context,
                 ),
+                file: String::new(),
                 block_stack: Vec::new(),
+                loop_stack: Vec::new(),
+                return_stack: Vec::new(),
                 local_stack: Vec::new(),
+                capture_stack: Vec::new(),
                 extern_functions: BTreeMap::new(),
                 runtime_variables: BTreeMap::new(),
+                engines: Vec::new(),
+                changed: true,
+                mains: Vec::new(),
+                ran: 0,
+                functions: BTreeMap::new(),
+                host_functions: Vec::new(),
+                runtime_error: None,
             });
 
             ctx.llvm_ctx_ptr = LLVMAddGlobal(
@@ -83,44 +239,80 @@ impl Context {
             );
             ctx.self_ptr = &*ctx as *const Context;
 
+            // the `push` method of arrays, see `path::child`
+            ctx.functions.insert(array_append as *const () as usize, 1);
+
             ctx.add_fn("__global_get", global_get as *mut _, 2);
-            // ctx.add_fn("__global_get_func", global_get_func as *mut _, 2);
             ctx.add_fn("__global_set", global_set as *mut _, 3);
-            ctx.add_fn("__add", add as *mut _, 2);
-            ctx.add_fn("__sub", sub as *mut _, 2);
-            ctx.add_fn("__mul", mul as *mut _, 2);
-            ctx.add_fn("__div", div as *mut _, 2);
+            ctx.add_fn("__value_get", value_get as *mut _, 3);
+            ctx.add_fn("__value_set", value_set as *mut _, 4);
+            ctx.add_fn("__add", add as *mut _, 3);
+            ctx.add_fn("__sub", sub as *mut _, 3);
+            ctx.add_fn("__mul", mul as *mut _, 3);
+            ctx.add_fn("__div", div as *mut _, 3);
+            ctx.add_fn("__mod", modulo as *mut _, 3);
+            ctx.add_fn("__neg", neg as *mut _, 2);
+            ctx.add_fn("__pos", pos as *mut _, 2);
+            ctx.add_fn("__not", not as *mut _, 1);
+            ctx.add_fn("__eq", eq as *mut _, 2);
+            ctx.add_fn("__neq", neq as *mut _, 2);
+            ctx.add_fn("__gt", gt as *mut _, 2);
+            ctx.add_fn("__le", le as *mut _, 2);
+            ctx.add_fn("__gte", gte as *mut _, 2);
+            ctx.add_fn("__lee", lee as *mut _, 2);
             ctx.add_fn("__array_new", array_new as *mut _, 0);
             ctx.add_fn("__array_push", array_push as *mut _, 2);
             ctx.add_fn("__dict_new", dict_new as *mut _, 0);
             ctx.add_fn("__dict_insert", dict_insert as *mut _, 3);
             ctx.add_fn("__dict_remove", dict_remove as *mut _, 2);
             ctx.add_fn("__string_new", string_new as *mut _, 0);
+            ctx.add_fn("__null_new", null_new as *mut _, 0);
             // ctx.add_fn("__string_from", string_from as *mut _, 1);
-            ctx.add_fn("__lambda_new", lambda_new as *mut _, 1);
+            ctx.add_fn("__closure_new", closure_new as *mut _, 2);
+            ctx.add_fn("__value_clone", value_clone as *mut _, 1);
             ctx.add_fn("__value_delete", value_delete as *mut _, 1);
+            ctx.add_fn("__value_result", value_result as *mut _, 1);
 
             {
-                let mut args = Vec::new();
-                args.push(ctx.llvm_ptr);
-                args.push(ctx.llvm_ptr);
+                let args = [
+                    ctx.llvm_ptr,
+                    ctx.llvm_ptr,
+                    ctx.llvm_ptr,
+                    LLVMInt64TypeInContext(context),
+                    LLVMPointerType(ctx.llvm_ptr, 0),
+                ];
 
                 let ft = LLVMFunctionType(LLVMInt64TypeInContext(context), args.as_ptr() as *mut _, args.len() as u32, 0);
                 let func = LLVMAddFunction(
                     ctx.llvm_module,
-                    CString::new("__global_get_func").unwrap().as_ptr(),
+                    CString::new("__callee").unwrap().as_ptr(),
                     ft,
                 );
 
-                ctx.extern_functions.insert("__global_get_func".to_string(), (
+                ctx.extern_functions.insert("__callee".to_string(), (
                     func,
-                    global_get_func as *mut _,
+                    callee as *mut _,
                 ));
             }
 
             {
-                let mut args = Vec::new();
-                args.push(LLVMPointerType(LLVMInt8TypeInContext(context), 0));
+                let args = [ctx.llvm_ptr, LLVMInt64TypeInContext(context)];
+
+                let ft = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
+                let func = LLVMAddFunction(
+                    ctx.llvm_module,
+                    CString::new("__env_get").unwrap().as_ptr(),
+                    ft,
+                );
+
+                ctx.extern_functions.insert("__env_get".to_string(), (
+                    func,
+                    env_get as *mut _,
+                ));
+            }
+
+            {
+                let args = [LLVMPointerType(LLVMInt8TypeInContext(context), 0), LLVMInt64TypeInContext(context)];
 
                 let ft = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
                 let func = LLVMAddFunction(
@@ -136,8 +328,7 @@ impl Context {
             }
 
             {
-                let mut args = Vec::new();
-                args.push(ctx.llvm_f64);
+                let args = [ctx.llvm_f64];
 
                 let ft = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
                 let func = LLVMAddFunction(
@@ -152,22 +343,100 @@ impl Context {
                 ));
             }
 
+            {
+                let args = [LLVMInt64TypeInContext(context)];
+
+                let ft = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
+                let func = LLVMAddFunction(
+                    ctx.llvm_module,
+                    CString::new("__int_new").unwrap().as_ptr(),
+                    ft,
+                );
+
+                ctx.extern_functions.insert("__int_new".to_string(), (
+                    func,
+                    int_new as *mut _,
+                ));
+            }
+
+            {
+                let args = [LLVMInt8TypeInContext(context)];
+
+                let ft = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
+                let func = LLVMAddFunction(
+                    ctx.llvm_module,
+                    CString::new("__bool_new").unwrap().as_ptr(),
+                    ft,
+                );
+
+                ctx.extern_functions.insert("__bool_new".to_string(), (
+                    func,
+                    bool_new as *mut _,
+                ));
+            }
+
+            {
+                let args = [ctx.llvm_ptr];
+
+                let ft = LLVMFunctionType(LLVMInt8TypeInContext(context), args.as_ptr() as *mut _, args.len() as u32, 0);
+                let func = LLVMAddFunction(
+                    ctx.llvm_module,
+                    CString::new("__truthy").unwrap().as_ptr(),
+                    ft,
+                );
+
+                ctx.extern_functions.insert("__truthy".to_string(), (
+                    func,
+                    truthy as *mut _,
+                ));
+            }
+
+            {
+                let i64_t = LLVMInt64TypeInContext(context);
+                let args = [ctx.llvm_ptr, i64_t, i64_t, LLVMPointerType(ctx.llvm_ptr, 0)];
+
+                let ft = LLVMFunctionType(ctx.llvm_ptr, args.as_ptr() as *mut _, args.len() as u32, 0);
+                let func = LLVMAddFunction(
+                    ctx.llvm_module,
+                    CString::new("__host_call").unwrap().as_ptr(),
+                    ft,
+                );
+
+                ctx.extern_functions.insert("__host_call".to_string(), (
+                    func,
+                    host_call as *mut _,
+                ));
+            }
+
             ctx
         }
     }
 
-    pub fn read_file(&mut self, filename: &str) {
-        let mut file = File::open(filename).unwrap();
+    /// Compile a script file into the module.
+    pub fn read_file(&mut self, filename: &str) -> Result<(), Error> {
+        let mut file = File::open(filename)?;
         let mut source = String::new();
 
-        file.read_to_string(&mut source).unwrap();
+        file.read_to_string(&mut source)?;
+
+        self.compile_str(filename, &source)
+    }
 
-        let pair = RunjitParser::parse(Rule::input, &source)
-            .unwrap_or_else(|e| panic!("{}", e))
+    /// Compile a script from memory into the module, `name` is used as file name in errors.
+    ///
+    /// If the script has errors, everything built from it so far is thrown away,
+    /// so the context stays usable.
+    pub fn compile_str(&mut self, name: &str, source: &str) -> Result<(), Error> {
+        let pair = RunjitParser::parse(Rule::input, source)
+            .map_err(|e| Error::syntax(name, e))?
             .next()
             .unwrap();
 
+        self.file = name.to_string();
+
         unsafe {
+            let last_func = LLVMGetLastFunction(self.llvm_module);
+
             let main_func_t = LLVMFunctionType(LLVMVoidTypeInContext(self.llvm_ctx), ptr::null_mut(), 0, 0);
             let main = LLVMAddFunction(
                 self.llvm_module,
@@ -185,27 +454,70 @@ impl Context {
 
             LLVMPositionBuilderAtEnd(self.llvm_builder, bb);
 
-            build::consume(self, pair);
+            if let Err(e) = build::consume(self, pair) {
+                self.discard_functions(last_func);
+                return Err(e);
+            }
 
             LLVMBuildRetVoid(self.llvm_builder);
 
-            LLVMDisposeBuilder(self.llvm_builder);
+            self.block_stack.clear();
 
             debug!(target: "runjit", "verify module");
-            // let mut buffer = 0 as *mut i8;
-            LLVMVerifyModule(self.llvm_module, LLVMVerifierFailureAction::LLVMAbortProcessAction, 0 as *mut _);
-            // LLVMVerifyModule(self.llvm_module, LLVMVerifierFailureAction::LLVMPrintMessageAction, &mut buffer);
-            // let msg = unsafe { CString::from_raw(buffer) };
-            // println!("-- error --\n{:?}", msg);
-
-            debug!(target: "runjit", "-- dump --");
-            let data = LLVMPrintModuleToString(self.llvm_module);
-            let cast = CStr::from_ptr(data);
-            let mut dump = File::create("main.ir").unwrap();
-            dump.write(cast.to_bytes()).unwrap();
+            let mut msg = ptr::null_mut();
+
+            if LLVMVerifyModule(self.llvm_module, LLVMVerifierFailureAction::LLVMReturnStatusAction, &mut msg) != 0 {
+                let err = Error::Verify(CStr::from_ptr(msg).to_string_lossy().into_owned());
+                LLVMDisposeMessage(msg);
+                self.discard_functions(last_func);
+                return Err(err);
+            }
+
+            LLVMDisposeMessage(msg);
+
+            self.mains.push(CStr::from_ptr(LLVMGetValueName(main)).to_owned());
+            self.changed = true;
+
+            if log_enabled!(target: "runjit.ir", Level::Trace) {
+                let data = LLVMPrintModuleToString(self.llvm_module);
+                trace!(target: "runjit.ir", "{}", CStr::from_ptr(data).to_string_lossy());
+                LLVMDisposeMessage(data);
+            }
         }
+
+        Ok(())
     }
 
+    /// Delete all functions added to the module after `last`, and reset the build state.
+    unsafe fn discard_functions(&mut self, last: LLVMValueRef) {
+        let mut func = if last.is_null() {
+            LLVMGetFirstFunction(self.llvm_module)
+        } else {
+            LLVMGetNextFunction(last)
+        };
+
+        while !func.is_null() {
+            let next = LLVMGetNextFunction(func);
+
+            LLVMReplaceAllUsesWith(func, LLVMGetUndef(LLVMTypeOf(func)));
+            LLVMDeleteFunction(func);
+
+            func = next;
+        }
+
+        self.block_stack.clear();
+        self.local_stack.clear();
+        self.capture_stack.clear();
+        self.loop_stack.clear();
+        self.return_stack.clear();
+    }
+
+    /// Make the native function `f` with `cnt` arguments callable as `name` from scripts.
+    ///
+    /// `f` has to be an `unsafe extern "C" fn(*const Value, ...) -> *const Value`.
+    /// The arguments are borrowed, they must not be dropped or kept without taking a new reference.
+    /// The result is handed over to the script, it is either a reference from `Rc::into_raw`
+    /// or a null pointer if there is nothing to return, which the script sees as `null`.
     pub fn add_fn(&mut self, name: &str, f: *mut libc::c_void, cnt: u32) {
         let mut args = Vec::new();
         for _ in 0..cnt {
@@ -218,44 +530,303 @@ impl Context {
         };
 
         self.extern_functions.insert(name.to_string(), (func, f));
+        self.changed = true;
+    }
+
+    /// Make the rust function or closure `f` callable as `name` from scripts.
+    ///
+    /// The arguments are converted to the parameter types of `f` when it is called,
+    /// see `Param`. A wrong number of arguments or an argument of the wrong type
+    /// is an error, as is an error returned by `f`. The script continues with `Null`
+    /// as result, and `run` or `call` reports the first error.
+    ///
+    /// ```rust,no_run
+    /// # use runjit::jit::{Context, Value};
+    /// # use runjit::Error;
+    /// let mut ctx = Context::new();
+    ///
+    /// ctx.register("repeat", |s: &str, n: f64| -> Result<Value, Error> {
+    ///     Ok(Value::string(s.repeat(n as usize)))
+    /// });
+    /// ```
+    ///
+    /// Panics if there already is a function called `name`.
+    pub fn register<F, Args>(&mut self, name: &str, f: F)
+    where
+        F: HostFn<Args> + 'static,
+        Args: 'static,
+    {
+        let arity = f.arity();
+        self.add_host_function(name, Some(arity), Box::new(move |args| f.call(args)));
+    }
+
+    /// Make a closure created with `types::RustCall::new` callable as `name` from scripts.
+    ///
+    /// The closure gets all arguments of a call, however many there are, converted with
+    /// `to_executor`. As the number of parameters is unknown, the function can only be called
+    /// by its name, it is not available as a lambda value.
+    ///
+    /// Panics if there already is a function called `name`.
+    pub fn add_call(&mut self, name: &str, call: Rc<types::Value>) -> Result<(), Error> {
+        match *call {
+            types::Value::RustCall(_) => {}
+            _ => return Err(Error::Type(String::from("expected a rust call"))),
+        }
+
+        self.add_host_function(name, None, Box::new(move |args| {
+            let args = args.iter().map(|a| to_executor(a).map(Rc::new)).collect::<Result<types::Args, Error>>()?;
+
+            match *call {
+                types::Value::RustCall(ref f) => from_executor(&*f.call(&args).map_err(Error::Call)?),
+                _ => unreachable!(),
+            }
+        }));
+
+        Ok(())
+    }
+
+    fn add_host_function(&mut self, name: &str, arity: Option<usize>, f: HostClosure) {
+        if self.extern_functions.contains_key(name) || self.host_function(name).is_some() {
+            panic!("`{}` is already registered", name);
+        }
+
+        let id = self.host_functions.len();
+
+        self.host_functions.push(HostFunction {
+            name: name.to_string(),
+            arity,
+            f,
+        });
+
+        if let Some(arity) = arity {
+            unsafe { build::host_function(self, name, id, arity) };
+        }
+
+        self.changed = true;
     }
 
-    pub fn run(&mut self) {
+    /// The id of the host function `name`, see `register`.
+    fn host_function(&self, name: &str) -> Option<usize> {
+        self.host_functions.iter().position(|h| h.name == name)
+    }
+
+    /// Execute the scripts compiled since the last run, in the order they were compiled.
+    ///
+    /// Scripts can be compiled and globals can be `set` between runs, every run sees the
+    /// current globals. The generated code is kept until the context is dropped,
+    /// so lambdas of the scripts can be invoked later with `call`. The first run after
+    /// a compile creates a new execution engine, the older ones are kept as well.
+    ///
+    /// Fails with the first error of a host function, see `register`, or of an access
+    /// like `a.b[3]` to a missing key or an index out of range.
+    pub fn run(&mut self) -> Result<(), Error> {
+        let start = self.ran;
+        self.execute(start)
+    }
+
+    /// Execute all compiled scripts again, in the order they were compiled, see `run`.
+    pub fn run_all(&mut self) -> Result<(), Error> {
+        self.execute(0)
+    }
+
+    fn execute(&mut self, start: usize) -> Result<(), Error> {
         unsafe {
-            let mut ee = mem::uninitialized();
-            let mut out = mem::zeroed();
+            if self.changed {
+                self.create_engine();
+            }
 
-            LLVMLinkInMCJIT();
-            LLVM_InitializeNativeTarget();
-            LLVM_InitializeNativeAsmPrinter();
+            let ee = *self.engines.last().unwrap();
 
-            LLVMCreateExecutionEngineForModule(&mut ee, self.llvm_module, &mut out);
+            for main in &self.mains[start..] {
+                let addr = LLVMGetFunctionAddress(ee, main.as_ptr());
 
-            LLVMAddGlobalMapping(ee, self.llvm_ctx_ptr, self.self_ptr as *mut _);
+                let f: extern "C" fn() = mem::transmute(addr);
 
-            for (name, &mut (valref, func)) in self.extern_functions.iter_mut() {
-                LLVMAddGlobalMapping(ee, valref, func);
+                f();
+            }
+        }
 
-                self.runtime_variables.insert(
-                    CString::new(name.as_bytes()).unwrap(),
-                    Rc::new(Value::Lambda(func as usize))
-                );
+        self.ran = self.mains.len();
+
+        match self.runtime_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// JIT compile a copy of the module, so the module itself stays open for more scripts.
+    ///
+    /// Superseded engines are not disposed, lambdas and closures handed out by their scripts
+    /// point into their code, so memory grows with every `compile_str` followed by a run.
+    /// Functions become globals, unless the script already assigned a global of that name.
+    unsafe fn create_engine(&mut self) {
+        let mut ee = ptr::null_mut();
+        let mut out = mem::zeroed();
+
+        LLVMLinkInMCJIT();
+        LLVM_InitializeNativeTarget();
+        LLVM_InitializeNativeAsmPrinter();
+
+        let module = LLVMCloneModule(self.llvm_module);
+
+        LLVMCreateExecutionEngineForModule(&mut ee, module, &mut out);
+
+        LLVMAddGlobalMapping(
+            ee,
+            LLVMGetNamedGlobal(module, b"__context\0".as_ptr() as *const _),
+            self.self_ptr as *mut _,
+        );
+
+        for (name, &mut (valref, func)) in self.extern_functions.iter_mut() {
+            let cname = CString::new(name.as_bytes()).unwrap();
+
+            LLVMAddGlobalMapping(ee, LLVMGetNamedFunction(module, cname.as_ptr()), func);
+
+            // internal functions do not follow the calling convention of `call`
+            if name.starts_with("__") {
+                continue;
             }
 
-            let addr = LLVMGetFunctionAddress(ee, b"__main__\0".as_ptr() as *const _);
+            self.runtime_variables
+                .entry(name.clone())
+                .or_insert_with(|| Rc::new(Value::Lambda(func as usize)));
+            self.functions.insert(func as usize, LLVMCountParams(valref) as usize);
+        }
 
-            let f: extern "C" fn() = mem::transmute(addr);
+        for host in &self.host_functions {
+            let arity = match host.arity {
+                Some(arity) => arity,
+                None => continue,
+            };
 
-            f();
+            let cname = CString::new(host.name.as_bytes()).unwrap();
+            let addr = LLVMGetFunctionAddress(ee, cname.as_ptr()) as usize;
 
-            LLVMDisposeExecutionEngine(ee);
+            self.runtime_variables
+                .entry(host.name.clone())
+                .or_insert_with(|| Rc::new(Value::Lambda(addr)));
+            self.functions.insert(addr, arity);
         }
+
+        let mut func = LLVMGetFirstFunction(module);
+
+        while !func.is_null() {
+            let name = CStr::from_ptr(LLVMGetValueName(func));
+
+            // lambdas take their environment first, see `Value::Closure`
+            if LLVMIsDeclaration(func) == 0 && name.to_bytes().starts_with(b"__lambda") {
+                let addr = LLVMGetFunctionAddress(ee, name.as_ptr());
+                self.functions.insert(addr as usize, LLVMCountParams(func) as usize - 1);
+            }
+
+            func = LLVMGetNextFunction(func);
+        }
+
+        self.engines.push(ee);
+        self.changed = false;
     }
 
+    /// Call a lambda of the script, or a function added with `add_fn`, after `run`.
+    ///
+    /// `f` is either the name of a global variable or a `Value::Lambda` or `Value::Closure`,
+    /// the number of `args` has to match the parameters of the lambda.
+    /// `args` is a slice of values or a tuple of `IntoValue`s, like `(1.0, "a")`.
+    pub fn call<C: Callee, A: IntoArgs>(&mut self, f: C, args: A) -> Result<Value, Error> {
+        let args = args.into_args();
+
+        if self.engines.is_empty() {
+            return Err(Error::Call(String::from("the script has not been run yet")));
+        }
+
+        let callee = f.lambda(self)?;
+
+        let (addr, env) = match *callee {
+            Value::Lambda(addr) => (addr, None),
+            Value::Closure(addr, ref env) => (addr, Some(&**env as *const Vec<Rc<Value>> as *const Value)),
+            _ => unreachable!(),
+        };
+
+        let arity = match self.functions.get(&addr) {
+            Some(&arity) => arity,
+            None => return Err(Error::Call(format!("no lambda at {:#x}", addr))),
+        };
+
+        if arity != args.len() {
+            return Err(Error::Call(format!(
+                "lambda takes {} arguments, {} given",
+                arity,
+                args.len()
+            )));
+        }
+
+        if arity > MAX_CALL_ARGS {
+            return Err(Error::Call(format!(
+                "lambdas with more than {} parameters can not be called",
+                MAX_CALL_ARGS
+            )));
+        }
+
+        let args: Vec<Rc<Value>> = args.iter().cloned().map(Rc::new).collect();
+        let ptrs: Vec<*const Value> = env.into_iter().chain(args.iter().map(|a| &**a as *const Value)).collect();
+
+        unsafe {
+            let res = call_raw(addr, &ptrs);
+
+            if let Some(e) = self.runtime_error.take() {
+                value_delete(res);
+                return Err(e);
+            }
+
+            if res.is_null() {
+                return Ok(Value::Null);
+            }
+
+            let res = Rc::from_raw(res);
+            Ok(Rc::try_unwrap(res).unwrap_or_else(|res| (*res).clone()))
+        }
+    }
+
+    /// Like `call`, converting the result to `T`.
+    pub fn call_as<T: FromValue, C: Callee, A: IntoArgs>(&mut self, f: C, args: A) -> Result<T, Error> {
+        T::from_value(&self.call(f, args)?)
+    }
+
+    /// The global variable `name`, `None` if it was never set.
+    ///
+    /// A variable set to `null` is `Some(Value::Null)`, reading a missing variable in a
+    /// script is a runtime error.
     pub fn get(&self, name: &str) -> Option<Rc<Value>> {
-        self.runtime_variables
-            .get(&CString::new(name).unwrap())
-            .and_then(|v| Some(v.clone()))
+        self.runtime_variables.get(name).cloned()
+    }
+
+    /// Set the global variable `name`, scripts see it the next time they read it.
+    ///
+    /// This works before the first `run` as well as between runs.
+    pub fn set<V: IntoValue>(&mut self, name: &str, val: V) {
+        self.runtime_variables.insert(name.to_string(), Rc::new(val.into_value()));
+    }
+
+    /// Deserialize the global variable `name`, a missing variable is `Null`.
+    ///
+    /// Errors name the key path of the offending value, like `config.ports[1]`.
+    #[cfg(feature = "serde")]
+    pub fn get_as<T: ::serde::de::DeserializeOwned>(&self, name: &str) -> Result<T, Error> {
+        let val = self.get(name).unwrap_or_else(|| Rc::new(Value::Null));
+        serial::from_named(name, &val)
+    }
+
+    /// Serialize `val` and set a global variable for each of its fields, see `set`.
+    ///
+    /// `val` has to serialize to a dict, like a struct or a map.
+    #[cfg(feature = "serde")]
+    pub fn set_from<T: ::serde::Serialize + ?Sized>(&mut self, val: &T) -> Result<(), Error> {
+        match to_value(val)? {
+            Value::Dict(ref fields) => {
+                self.runtime_variables.extend(fields.borrow().clone());
+                Ok(())
+            }
+            ref other => Err(mismatch("dict", other)),
+        }
     }
 }
 
@@ -265,7 +836,33 @@ impl Drop for Context {
             // debug!("drop module");
             // LLVMDisposeModule(self.llvm_module);
             debug!(target: "runjit", "drop context");
+
+            // every engine owns its copy of the module
+            for ee in self.engines.drain(..) {
+                LLVMDisposeExecutionEngine(ee);
+            }
+
+            LLVMDisposeBuilder(self.llvm_builder);
             LLVMContextDispose(self.llvm_ctx);
         }
     }
 }
+
+/// Call the function at `addr` with borrowed `args`, see `callbacks` for the protocol.
+///
+/// The environment of a closure is passed as its first argument.
+unsafe fn call_raw(addr: usize, args: &[*const Value]) -> *const Value {
+    type V = *const Value;
+
+    match *args {
+        [] => mem::transmute::<usize, extern "C" fn() -> V>(addr)(),
+        [a] => mem::transmute::<usize, extern "C" fn(V) -> V>(addr)(a),
+        [a, b] => mem::transmute::<usize, extern "C" fn(V, V) -> V>(addr)(a, b),
+        [a, b, c] => mem::transmute::<usize, extern "C" fn(V, V, V) -> V>(addr)(a, b, c),
+        [a, b, c, d] => mem::transmute::<usize, extern "C" fn(V, V, V, V) -> V>(addr)(a, b, c, d),
+        [a, b, c, d, e] => mem::transmute::<usize, extern "C" fn(V, V, V, V, V) -> V>(addr)(a, b, c, d, e),
+        [a, b, c, d, e, f] => mem::transmute::<usize, extern "C" fn(V, V, V, V, V, V) -> V>(addr)(a, b, c, d, e, f),
+        [a, b, c, d, e, f, g] => mem::transmute::<usize, extern "C" fn(V, V, V, V, V, V, V) -> V>(addr)(a, b, c, d, e, f, g),
+        _ => panic!("too many arguments: {}", args.len()),
+    }
+}
@@ -0,0 +1,137 @@
+//!
+//! Access paths like `a.b[3].c`, built by `build_access_array` as an array of parts.
+//!
+//! The first part is the name of a global variable, every following part is either a string,
+//! the key of a dict, or a whole number, the index of an array, usually an int. Nothing is created on the way,
+//! a missing key or an index out of range is an error, only assigning inserts a last new key.
+//! Arrays have a `push` method, `a.push` is a closure appending to `a`.
+//!
+use std::rc::Rc;
+
+use error::Error;
+use jit::Value;
+use jit::callbacks::array_append;
+
+enum Step<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// The path up to and including `parts[i]`, for error messages.
+fn describe(parts: &[Rc<Value>], i: usize) -> String {
+    let mut path = String::new();
+
+    for (n, part) in parts[..=i].iter().enumerate() {
+        match **part {
+            Value::Str(ref s) if n == 0 => path.push_str(s),
+            Value::Str(ref s) => {
+                path.push('.');
+                path.push_str(s);
+            }
+            Value::Int(i) => path.push_str(&format!("[{}]", i)),
+            Value::Float(f) => path.push_str(&format!("[{}]", f)),
+            ref other => path.push_str(&format!("[{}]", other.type_name())),
+        }
+    }
+
+    path
+}
+
+/// The whole path, like `a.b[3]`.
+pub fn name(parts: &[Rc<Value>]) -> String {
+    describe(parts, parts.len() - 1)
+}
+
+fn step(parts: &[Rc<Value>], i: usize) -> Result<Step<'_>, Error> {
+    match *parts[i] {
+        Value::Str(ref s) => Ok(Step::Key(s)),
+        Value::Int(n) if n >= 0 => Ok(Step::Index(n as usize)),
+        Value::Int(n) => Err(Error::Runtime(format!(
+            "invalid index {} in `{}`",
+            n,
+            describe(parts, i)
+        ))),
+        Value::Float(f) if f >= 0.0 && f.fract() == 0.0 => Ok(Step::Index(f as usize)),
+        Value::Float(f) => Err(Error::Runtime(format!(
+            "invalid index {} in `{}`",
+            f,
+            describe(parts, i)
+        ))),
+        ref other => Err(Error::Runtime(format!(
+            "can not index with {} in `{}`",
+            other.type_name(),
+            describe(parts, i)
+        ))),
+    }
+}
+
+fn missing_key(parts: &[Rc<Value>], i: usize) -> Error {
+    Error::Runtime(format!("no key `{}`", describe(parts, i)))
+}
+
+fn out_of_range(parts: &[Rc<Value>], i: usize, len: usize) -> Error {
+    Error::Runtime(format!(
+        "index out of range in `{}`, `{}` has {} elements",
+        describe(parts, i),
+        describe(parts, i - 1),
+        len
+    ))
+}
+
+fn not_indexable(parts: &[Rc<Value>], i: usize, val: &Value) -> Error {
+    Error::Runtime(format!(
+        "can not access `{}`, `{}` is {}",
+        describe(parts, i),
+        describe(parts, i - 1),
+        val.type_name()
+    ))
+}
+
+fn child(val: &Value, parts: &[Rc<Value>], i: usize) -> Result<Rc<Value>, Error> {
+    match (step(parts, i)?, val) {
+        (Step::Key(k), Value::Dict(d)) => d.borrow().get(k).cloned().ok_or_else(|| missing_key(parts, i)),
+        (Step::Key("push"), Value::Array(a)) => {
+            let this = Rc::new(Value::Array(a.clone()));
+            Ok(Rc::new(Value::Closure(array_append as *const () as usize, Rc::new(vec![this]))))
+        }
+        (Step::Index(n), Value::Array(a)) => {
+            let a = a.borrow();
+            a.get(n).cloned().ok_or_else(|| out_of_range(parts, i, a.len()))
+        }
+        (_, other) => Err(not_indexable(parts, i, other)),
+    }
+}
+
+/// The value at `parts[1..]` inside of `root`, the global `parts[0]`.
+pub fn lookup(root: &Rc<Value>, parts: &[Rc<Value>]) -> Result<Rc<Value>, Error> {
+    let mut val = root.clone();
+
+    for i in 1..parts.len() {
+        val = child(&val, parts, i)?;
+    }
+
+    Ok(val)
+}
+
+/// Replace the value at `parts[1..]` inside of `root` with `new`.
+///
+/// Only the last key is inserted if it is missing, every dict and array on the way has to exist.
+/// Collections are shared, so the change is visible through every variable holding them.
+pub fn assign(root: &Rc<Value>, parts: &[Rc<Value>], new: Rc<Value>) -> Result<(), Error> {
+    let last = parts.len() - 1;
+    let parent = lookup(root, &parts[..last])?;
+
+    match (step(parts, last)?, &*parent) {
+        (Step::Key(k), Value::Dict(d)) => {
+            d.borrow_mut().insert(k.to_string(), new);
+        }
+        (Step::Index(n), Value::Array(a)) => {
+            let mut a = a.borrow_mut();
+            let len = a.len();
+            *a.get_mut(n).ok_or_else(|| out_of_range(parts, last, len))? = new;
+        }
+        (_, other) => return Err(not_indexable(parts, last, other)),
+    }
+
+    Ok(())
+}
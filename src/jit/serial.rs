@@ -0,0 +1,705 @@
+//!
+//! serde support, serialize rust values into script values and deserialize them back.
+//!
+//! Only compiled with the `serde` feature.
+//!
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::iter;
+use std::rc::Rc;
+use std::vec;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::ser::{self, Serialize};
+
+use error::Error;
+use jit::{FromValue, IntoValue, Value};
+
+/// Convert `val` into a script value.
+pub fn to_value<T: Serialize + ?Sized>(val: &T) -> Result<Value, Error> {
+    val.serialize(Serializer).map_err(PathError::into_error)
+}
+
+/// Convert a script value into `T`, errors name the key path of the offending value.
+pub fn from_value<T: DeserializeOwned>(val: &Value) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(val)).map_err(PathError::into_error)
+}
+
+/// Like `from_value`, with `name` as the start of the key path.
+pub(crate) fn from_named<T: DeserializeOwned>(name: &str, val: &Value) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(val))
+        .map_err(|e| e.at(Segment::Key(String::from(name))).into_error())
+}
+
+#[derive(Debug)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A message and the path to where it happened, innermost segment first.
+#[derive(Debug)]
+struct PathError {
+    msg: String,
+    path: Vec<Segment>,
+}
+
+impl PathError {
+    fn new<S: Into<String>>(msg: S) -> PathError {
+        PathError {
+            msg: msg.into(),
+            path: Vec::new(),
+        }
+    }
+
+    fn at(mut self, segment: Segment) -> PathError {
+        self.path.push(segment);
+        self
+    }
+
+    fn into_error(self) -> Error {
+        if self.path.is_empty() {
+            return Error::Type(self.msg);
+        }
+
+        let mut path = String::new();
+
+        for segment in self.path.iter().rev() {
+            match *segment {
+                Segment::Key(ref key) if path.is_empty() => path.push_str(key),
+                Segment::Key(ref key) => {
+                    path.push('.');
+                    path.push_str(key);
+                }
+                Segment::Index(i) => path.push_str(&format!("[{}]", i)),
+            }
+        }
+
+        Error::Type(format!("{} at `{}`", self.msg, path))
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl error::Error for PathError {}
+
+impl ser::Error for PathError {
+    fn custom<T: fmt::Display>(msg: T) -> PathError {
+        PathError::new(msg.to_string())
+    }
+}
+
+impl de::Error for PathError {
+    fn custom<T: fmt::Display>(msg: T) -> PathError {
+        PathError::new(msg.to_string())
+    }
+}
+
+impl From<Error> for PathError {
+    fn from(err: Error) -> PathError {
+        match err {
+            Error::Type(msg) => PathError::new(msg),
+            other => PathError::new(other.to_string()),
+        }
+    }
+}
+
+/// A dict with the single entry `variant: val`, how enum variants with data are stored.
+fn variant(name: &'static str, val: Value) -> Value {
+    Value::Dict(Rc::new(RefCell::new(
+        iter::once((String::from(name), Rc::new(val))).collect(),
+    )))
+}
+
+impl Serialize for Value {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        match *self {
+            Value::Array(ref a) => {
+                let a = a.borrow();
+                let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                for v in a.iter() {
+                    seq.serialize_element(&**v)?;
+                }
+                seq.end()
+            }
+            Value::Dict(ref d) => {
+                let d = d.borrow();
+                let mut map = serializer.serialize_map(Some(d.len()))?;
+                for (k, v) in d.iter() {
+                    map.serialize_entry(k, &**v)?;
+                }
+                map.end()
+            }
+            Value::Lambda(_) | Value::Closure(..) => Err(ser::Error::custom("lambdas can not be serialized")),
+            Value::Bool(b) => serializer.serialize_bool(b),
+            Value::Int(i) => serializer.serialize_i64(i),
+            Value::Float(f) => serializer.serialize_f64(f),
+            Value::Str(ref s) => serializer.serialize_str(s),
+            Value::Null => serializer.serialize_unit(),
+        }
+    }
+}
+
+struct Serializer;
+
+struct SerializeArray {
+    array: Vec<Rc<Value>>,
+    variant: Option<&'static str>,
+}
+
+struct SerializeDict {
+    dict: BTreeMap<String, Rc<Value>>,
+    key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeArray {
+    fn element<T: Serialize + ?Sized>(&mut self, val: &T) -> Result<(), PathError> {
+        let val = val
+            .serialize(Serializer)
+            .map_err(|e| e.at(Segment::Index(self.array.len())))?;
+
+        self.array.push(Rc::new(val));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, PathError> {
+        let array = Value::Array(Rc::new(RefCell::new(self.array)));
+
+        Ok(match self.variant {
+            Some(name) => variant(name, array),
+            None => array,
+        })
+    }
+}
+
+impl SerializeDict {
+    fn entry<T: Serialize + ?Sized>(&mut self, key: String, val: &T) -> Result<(), PathError> {
+        let val = val
+            .serialize(Serializer)
+            .map_err(|e| e.at(Segment::Key(key.clone())))?;
+
+        self.dict.insert(key, Rc::new(val));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, PathError> {
+        let dict = Value::Dict(Rc::new(RefCell::new(self.dict)));
+
+        Ok(match self.variant {
+            Some(name) => variant(name, dict),
+            None => dict,
+        })
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = PathError;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeArray;
+    type SerializeMap = SerializeDict;
+    type SerializeStruct = SerializeDict;
+    type SerializeStructVariant = SerializeDict;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, PathError> {
+        Ok(v.into_value())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, PathError> {
+        Ok(Value::Float(f64::from(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, PathError> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, PathError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, PathError> {
+        Ok(Value::string(v))
+    }
+
+    /// Bytes are an array of ints, they do not have to be utf-8.
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, PathError> {
+        Ok(Value::array(v.iter().map(|&b| Value::Int(i64::from(b)))))
+    }
+
+    fn serialize_none(self) -> Result<Value, PathError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, PathError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, PathError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, PathError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, PathError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, PathError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value, PathError> {
+        let val = value
+            .serialize(self)
+            .map_err(|e| e.at(Segment::Key(String::from(name))))?;
+
+        Ok(variant(name, val))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, PathError> {
+        Ok(SerializeArray {
+            array: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, PathError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray, PathError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, PathError> {
+        Ok(SerializeArray {
+            array: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeDict, PathError> {
+        Ok(SerializeDict {
+            dict: BTreeMap::new(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeDict, PathError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeDict, PathError> {
+        Ok(SerializeDict {
+            dict: BTreeMap::new(),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeArray {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for SerializeDict {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), PathError> {
+        match key.serialize(Serializer)? {
+            Value::Str(ref key) => {
+                self.key = Some(key.clone());
+                Ok(())
+            }
+            ref other => Err(PathError::new(format!(
+                "dict keys have to be strings, got {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PathError> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        self.entry(key, value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeDict {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), PathError> {
+        self.entry(String::from(key), value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeDict {
+    type Ok = Value;
+    type Error = PathError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), PathError> {
+        self.entry(String::from(key), value)
+    }
+
+    fn end(self) -> Result<Value, PathError> {
+        self.finish()
+    }
+}
+
+/// Deserializes a value, arrays and dicts are read from a snapshot of their elements,
+/// so nothing stays borrowed while the visitor runs.
+struct Deserializer(Rc<Value>);
+
+impl Deserializer {
+    fn new(val: &Value) -> Deserializer {
+        Deserializer(Rc::new(val.clone()))
+    }
+
+    fn unexpected(&self) -> Unexpected<'_> {
+        match *self.0 {
+            Value::Array(_) => Unexpected::Seq,
+            Value::Dict(_) => Unexpected::Map,
+            Value::Lambda(_) | Value::Closure(..) => Unexpected::Other("lambda"),
+            Value::Bool(b) => Unexpected::Bool(b),
+            Value::Int(i) => Unexpected::Signed(i),
+            Value::Float(f) => Unexpected::Float(f),
+            Value::Str(ref s) => Unexpected::Str(s),
+            Value::Null => Unexpected::Unit,
+        }
+    }
+}
+
+macro_rules! deserialize_int {
+    ($($method:ident => $int:ty, $visit:ident;)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+            match *self.0 {
+                Value::Int(_) | Value::Float(_) => visitor.$visit(<$int>::from_value(&self.0)?),
+                _ => self.deserialize_any(visitor),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = PathError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        match *self.0 {
+            Value::Array(ref a) => visitor.visit_seq(ArrayAccess {
+                iter: a.borrow().clone().into_iter().enumerate(),
+            }),
+            Value::Dict(ref d) => visitor.visit_map(DictAccess {
+                iter: d.borrow().clone().into_iter(),
+                value: None,
+            }),
+            Value::Lambda(_) | Value::Closure(..) => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Str(ref s) => visitor.visit_str(s),
+            Value::Null => visitor.visit_unit(),
+        }
+    }
+
+    deserialize_int! {
+        deserialize_i8 => i8, visit_i8;
+        deserialize_i16 => i16, visit_i16;
+        deserialize_i32 => i32, visit_i32;
+        deserialize_i64 => i64, visit_i64;
+        deserialize_u8 => u8, visit_u8;
+        deserialize_u16 => u16, visit_u16;
+        deserialize_u32 => u32, visit_u32;
+        deserialize_u64 => u64, visit_u64;
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        match *self.0 {
+            Value::Str(ref s) => visitor.visit_bytes(s.as_bytes()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        match *self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are strings, variants with data a dict with a single entry.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        match *self.0 {
+            Value::Str(ref s) => {
+                let name: de::value::StrDeserializer<PathError> = s.as_str().into_deserializer();
+                visitor.visit_enum(name)
+            }
+            Value::Dict(ref d) if d.borrow().len() == 1 => {
+                let (name, val) = d.borrow().iter().next().map(|(k, v)| (k.clone(), v.clone())).unwrap();
+                visitor.visit_enum(VariantAccess {
+                    name,
+                    value: val,
+                })
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"a string or a dict with one entry")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char str string unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ArrayAccess {
+    iter: iter::Enumerate<vec::IntoIter<Rc<Value>>>,
+}
+
+impl<'de> de::SeqAccess<'de> for ArrayAccess {
+    type Error = PathError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, PathError> {
+        match self.iter.next() {
+            Some((i, val)) => seed
+                .deserialize(Deserializer(val))
+                .map(Some)
+                .map_err(|e| e.at(Segment::Index(i))),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct DictAccess {
+    iter: ::std::collections::btree_map::IntoIter<String, Rc<Value>>,
+    value: Option<(String, Rc<Value>)>,
+}
+
+impl<'de> de::MapAccess<'de> for DictAccess {
+    type Error = PathError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, PathError> {
+        match self.iter.next() {
+            Some((key, val)) => {
+                let res = {
+                    let key: de::value::StrDeserializer<PathError> = key.as_str().into_deserializer();
+                    seed.deserialize(key).map(Some)
+                };
+
+                self.value = Some((key, val));
+                res
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, PathError> {
+        let (key, val) = self.value.take().expect("next_value_seed called before next_key_seed");
+
+        seed.deserialize(Deserializer(val))
+            .map_err(|e| e.at(Segment::Key(key)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct VariantAccess {
+    name: String,
+    value: Rc<Value>,
+}
+
+impl VariantAccess {
+    fn at(name: String) -> impl Fn(PathError) -> PathError {
+        move |err| err.at(Segment::Key(name.clone()))
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for VariantAccess {
+    type Error = PathError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), PathError> {
+        let variant = {
+            let name: de::value::StrDeserializer<PathError> = self.name.as_str().into_deserializer();
+            seed.deserialize(name)?
+        };
+
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = PathError;
+
+    fn unit_variant(self) -> Result<(), PathError> {
+        de::Deserialize::deserialize(Deserializer(self.value)).map_err(VariantAccess::at(self.name))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, PathError> {
+        seed.deserialize(Deserializer(self.value)).map_err(VariantAccess::at(self.name))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, PathError> {
+        de::Deserializer::deserialize_seq(Deserializer(self.value), visitor).map_err(VariantAccess::at(self.name))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        de::Deserializer::deserialize_map(Deserializer(self.value), visitor).map_err(VariantAccess::at(self.name))
+    }
+}
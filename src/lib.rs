@@ -2,31 +2,36 @@
 //! **example.rj**
 //!
 //! ```js
-//! call = (x) => { print() }
-//! call()
+//! call = (x) => { print(x) }
+//! call("hello")
 //! ```
 //!
 //! **main.rs**
 //!
-//! ```rust
+//! ```rust,no_run
 //! extern crate runjit;
 //!
 //! use runjit::jit::Context;
-//!
-//! fn myprint() {
-//!     println!("in runjit");
-//! }
+//! use runjit::Error;
 //!
 //! fn main() {
 //!     let mut ctx = Context::new();
 //!
-//!     ctx.add_fn("print", myprint as *mut _, 0);
+//!     ctx.register("print", |s: &str| -> Result<(), Error> {
+//!         println!("in runjit {}", s);
+//!         Ok(())
+//!     });
 //!
-//!     ctx.read_file("example.rj");
+//!     if let Err(e) = ctx.read_file("example.rj") {
+//!         println!("{}", e);
+//!         return;
+//!     }
 //!
-//!     ctx.run();
+//!     if let Err(e) = ctx.run() {
+//!         println!("{}", e);
+//!     }
 //!
-//!     println!("{:?}", ctx.get_float("myvar"));
+//!     println!("{:?}", ctx.get("myvar"));
 //! }
 //! ```
 //!
@@ -37,9 +42,15 @@ extern crate llvm_sys as llvm;
 extern crate libc;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
 pub mod ast;
+pub mod error;
 pub mod executor;
 pub mod jit;
 pub mod parser;
 pub mod types;
+
+pub use error::Error;
@@ -1,5 +1,135 @@
+use pest::iterators::{Pair, Pairs};
+
+use std::iter::Peekable;
 
 //
 #[derive(Parser)]
 #[grammar = "runjit.pest"]
 pub struct RunjitParser;
+
+/// How strong a binary operator binds, higher binds stronger.
+pub fn precedence(rule: Rule) -> u8 {
+    match rule {
+        Rule::op_or => 1,
+        Rule::op_and => 2,
+        Rule::op_eq | Rule::op_neq | Rule::op_gt | Rule::op_le | Rule::op_gte | Rule::op_lee => 3,
+        Rule::op_add | Rule::op_sub => 4,
+        Rule::op_mul | Rule::op_div | Rule::op_mod => 5,
+        _ => panic!("not an operator: {:?}", rule),
+    }
+}
+
+/// Fold the flat `term (op term)*` list of an `exp` rule by operator precedence,
+/// all operators are left associative.
+///
+/// `primary` builds a single term and `infix` combines two built terms, both get `state`
+/// passed along, so the AST and the JIT builder can share this.
+pub fn climb<'i, S, T, P, I>(state: &mut S, pair: Pair<'i, Rule>, mut primary: P, mut infix: I) -> T
+where
+    P: FnMut(&mut S, Pair<'i, Rule>) -> T,
+    I: FnMut(&mut S, T, Pair<'i, Rule>, T) -> T,
+{
+    let mut pairs = pair.into_inner().peekable();
+    let first = pairs.next().unwrap();
+    let lhs = primary(state, first);
+
+    climb_rhs(state, lhs, 0, &mut pairs, &mut primary, &mut infix)
+}
+
+fn climb_rhs<'i, S, T, P, I>(
+    state: &mut S,
+    mut lhs: T,
+    min: u8,
+    pairs: &mut Peekable<Pairs<'i, Rule>>,
+    primary: &mut P,
+    infix: &mut I,
+) -> T
+where
+    P: FnMut(&mut S, Pair<'i, Rule>) -> T,
+    I: FnMut(&mut S, T, Pair<'i, Rule>, T) -> T,
+{
+    while pairs.peek().is_some_and(|op| precedence(op.as_rule()) >= min) {
+        let op = pairs.next().unwrap();
+        let prec = precedence(op.as_rule());
+        let mut rhs = primary(state, pairs.next().expect("incomplete expression"));
+
+        while let Some(next) = pairs.peek().map(|op| precedence(op.as_rule())).filter(|&p| p > prec) {
+            rhs = climb_rhs(state, rhs, next, pairs, primary, infix);
+        }
+
+        lhs = infix(state, lhs, op, rhs);
+    }
+
+    lhs
+}
+
+/// The value of the integer literal `s`, the error says it is too large.
+pub fn integer(s: &str) -> Result<i64, String> {
+    s.parse().map_err(|_| format!("integer literal {} is too large", s))
+}
+
+/// The source of the negative literal if the `unary` rule `pair` is a `-` in front of an integer
+/// literal, `-9223372036854775808` only fits if the minus is part of the literal.
+pub fn negative_integer(pair: &Pair<Rule>) -> Option<String> {
+    let mut inner = pair.clone().into_inner();
+    let op = inner.next().unwrap();
+    let operand = inner.next().unwrap();
+
+    if op.as_rule() == Rule::op_neg
+        && operand.as_rule() == Rule::literal
+        && operand.as_str().bytes().all(|b| b.is_ascii_digit())
+    {
+        Some(format!("-{}", operand.as_str()))
+    } else {
+        None
+    }
+}
+
+/// Replace the escapes in `s`, the content of a string literal, like `\n` or `\u00e9`.
+///
+/// The error names the invalid escape.
+pub fn unescape(s: &str) -> Result<String, String> {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+
+        let c = match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('f') => '\x0c',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('v') => '\x0b',
+            Some('u') => {
+                let hex: String = chars.clone().take(4).collect();
+                let code = if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                };
+
+                match code {
+                    Some(c) => {
+                        chars.nth(3);
+                        c
+                    }
+                    None => return Err(format!("invalid unicode escape `\\u{}`", hex)),
+                }
+            }
+            Some(other) => return Err(format!("invalid escape `\\{}`", other)),
+            None => return Err(String::from("incomplete escape at the end of the string")),
+        };
+
+        res.push(c);
+    }
+
+    Ok(res)
+}
@@ -4,16 +4,31 @@ use std::rc::Rc;
 use ast::Ast;
 
 pub enum Value {
+    Int(i64),
     Float(f64),
+    Bool(bool),
     String(String),
     Lambda(Vec<String>, Vec<Rc<Ast>>),
-    RustCall(Box<Call>),
+    RustCall(Box<dyn Call>),
     Null,
 }
 
+impl Value {
+    /// `Null`, `false`, `0` and `NaN` are false, everything else is true.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Null => false,
+            Value::Bool(b) => b,
+            Value::Int(i) => i != 0,
+            Value::Float(f) => f != 0.0 && !f.is_nan(),
+            _ => true,
+        }
+    }
+}
+
 pub trait Dict {}
 
-type Args = Vec<Rc<Value>>;
+pub type Args = Vec<Rc<Value>>;
 
 pub trait Call {
     fn call(&self, args: &Args) -> Result<Rc<Value>, String>;
@@ -31,6 +46,8 @@ where
     T: 'static,
     T: Fn(&Args) -> Result<Rc<Value>, String>,
 {
+    /// Wraps `f` into a `Value::RustCall`, ready to be `set` in an executor context.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(f: T) -> Rc<Value> {
         Rc::new(Value::RustCall(Box::new(RustCall { func: f })))
     }
@@ -9,6 +9,8 @@ endif
 
 syn keyword runjitKeywords if else loop
 syn match runjitKeywords '=>'
+syn keyword runjitBoolean true false
+syn keyword runjitNull null
 syn match runjitNumber '[-+]\d\+\.\d*'
 syn match runjitNumber '\d\+\.\d*'
 syn match runjitNumber '\d\+'
@@ -24,6 +26,8 @@ let b:current_syntax = "runjit"
 
 hi def link runjitNumber   Constant
 hi def link runjitString   Constant
+hi def link runjitBoolean  Constant
+hi def link runjitNull     Constant
 hi def link runjitKeywords Keyword
 hi def link runjitBlock    Statement
 hi def link runjitComment  Comment
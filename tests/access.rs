@@ -0,0 +1,127 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use runjit::Error;
+use common::runtime_error_in;
+
+fn context() -> Box<Context> {
+    let mut ctx = Context::new();
+
+    ctx.set(
+        "config",
+        Value::dict(vec![
+            ("name", Value::string("runjit")),
+            (
+                "servers",
+                Value::array(vec![
+                    Value::dict(vec![("port", Value::Float(80.0))]),
+                    Value::dict(vec![("port", Value::Float(8080.0))]),
+                ]),
+            ),
+        ]),
+    );
+
+    ctx
+}
+
+#[test]
+fn read() {
+    let mut ctx = context();
+
+    ctx.compile_str(
+        "test",
+        "i = 1\nname = config.name\nfirst = config.servers[0].port\nsecond = config.servers[i].port\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("name").unwrap(), Value::string("runjit"));
+    assert_eq!(*ctx.get("first").unwrap(), Value::Float(80.0));
+    assert_eq!(*ctx.get("second").unwrap(), Value::Float(8080.0));
+}
+
+#[test]
+fn write() {
+    let mut ctx = context();
+
+    ctx.compile_str(
+        "test",
+        "copy = config\nconfig.servers[1].port = 9000\nconfig.debug = 1\nconfig.name = \"other\"\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    let config = ctx.get("config").unwrap();
+    let servers = Value::array(vec![
+        Value::dict(vec![("port", Value::Float(80.0))]),
+        Value::dict(vec![("port", Value::Int(9000))]),
+    ]);
+
+    assert_eq!(
+        *config,
+        Value::dict(vec![
+            ("debug", Value::Int(1)),
+            ("name", Value::string("other")),
+            ("servers", servers),
+        ])
+    );
+
+    // collections are shared, not copied
+    assert_eq!(*ctx.get("copy").unwrap(), *config);
+}
+
+#[test]
+fn call() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = context();
+
+    {
+        let seen = seen.clone();
+        ctx.register("record", move |x: f64| seen.borrow_mut().push(x));
+    }
+
+    ctx.compile_str(
+        "test",
+        "double = (x) => { record(x * 2) }\nlist = [double]\nhandlers = { on: list }\nhandlers.on[0](21)\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*seen.borrow(), vec![42.0]);
+
+    ctx.compile_str("other", "config.name()\nx = 1\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "can not call `config.name`, it is string"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    assert_eq!(*ctx.get("x").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(runtime_error_in(context(), "x = config.missing\n"), "no key `config.missing`");
+    assert_eq!(runtime_error_in(context(), "x = config.servers[0].host\n"), "no key `config.servers[0].host`");
+    assert_eq!(
+        runtime_error_in(context(), "x = config.servers[2]\n"),
+        "index out of range in `config.servers[2]`, `config.servers` has 2 elements"
+    );
+    assert_eq!(
+        runtime_error_in(context(), "config.servers[5].port = 1\n"),
+        "index out of range in `config.servers[5]`, `config.servers` has 2 elements"
+    );
+    assert_eq!(
+        runtime_error_in(context(), "config.servers.port = 1\n"),
+        "can not access `config.servers.port`, `config.servers` is array"
+    );
+    assert_eq!(
+        runtime_error_in(context(), "config.name[0] = 1\n"),
+        "can not access `config.name[0]`, `config.name` is string"
+    );
+    assert_eq!(runtime_error_in(context(), "x = config.servers[0.5]\n"), "invalid index 0.5 in `config.servers[0.5]`");
+    assert_eq!(runtime_error_in(context(), "x = missing.key\n"), "`missing` is not defined");
+    assert_eq!(runtime_error_in(context(), "missing.key = 1\n"), "`missing` is not defined");
+    assert_eq!(runtime_error_in(context(), "config.other.key = 1\n"), "no key `config.other`");
+}
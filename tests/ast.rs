@@ -0,0 +1,178 @@
+extern crate runjit;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::ast::{self, Ast};
+use runjit::executor;
+use runjit::jit::Context;
+use runjit::types::{self, Args, RustCall};
+use runjit::Error;
+
+fn statements(source: &str) -> Vec<Rc<Ast>> {
+    match Rc::try_unwrap(ast::read_str("test", source).unwrap()) {
+        Ok(Ast::Block(stmnts)) => stmnts,
+        other => panic!("expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn loops() {
+    let stmnts = statements(
+        "f = (n) => { loop n > 0 { n = n - 1\nif n == 5 { continue }\nif n == 2 { break } } }\nloop { break }\n",
+    );
+
+    let body = match *stmnts[0] {
+        Ast::Assign(_, ref lambda) => match **lambda {
+            Ast::Lambda(_, ref body) => body,
+            ref other => panic!("expected lambda, got {:?}", other),
+        },
+        ref other => panic!("expected assign, got {:?}", other),
+    };
+
+    match *body[0] {
+        Ast::Loop(ref cond, ref block) => {
+            match **cond {
+                Ast::Exp(..) => {}
+                ref other => panic!("expected condition, got {:?}", other),
+            }
+            match (&*block[1], &*block[2]) {
+                (Ast::If(_, ref c, _), Ast::If(_, ref b, _)) => match (&*c[0], &*b[0]) {
+                    (Ast::Continue, Ast::Break) => {}
+                    other => panic!("expected continue and break, got {:?}", other),
+                },
+                other => panic!("expected ifs, got {:?}", other),
+            }
+        }
+        ref other => panic!("expected loop, got {:?}", other),
+    }
+
+    match *stmnts[1] {
+        Ast::Loop(ref cond, ref block) => match (&**cond, &*block[0]) {
+            (Ast::Nothing, Ast::Break) => {}
+            other => panic!("expected endless loop, got {:?}", other),
+        },
+        ref other => panic!("expected loop, got {:?}", other),
+    }
+}
+
+#[test]
+fn returns() {
+    let stmnts = statements("f = (n) => { loop n > 0 { return n }\nreturn }\n");
+
+    let body = match *stmnts[0] {
+        Ast::Assign(_, ref lambda) => match **lambda {
+            Ast::Lambda(_, ref body) => body,
+            ref other => panic!("expected lambda, got {:?}", other),
+        },
+        ref other => panic!("expected assign, got {:?}", other),
+    };
+
+    match *body[0] {
+        Ast::Loop(_, ref block) => match *block[0] {
+            Ast::Return(ref val) => match **val {
+                Ast::Var(_) => {}
+                ref other => panic!("expected var, got {:?}", other),
+            },
+            ref other => panic!("expected return, got {:?}", other),
+        },
+        ref other => panic!("expected loop, got {:?}", other),
+    }
+
+    match *body[1] {
+        Ast::Return(ref val) => match **val {
+            Ast::Null => {}
+            ref other => panic!("expected null, got {:?}", other),
+        },
+        ref other => panic!("expected return, got {:?}", other),
+    }
+}
+
+#[test]
+fn else_branches() {
+    let stmnts = statements("if a { x = 1 } else if b { x = 2 } else if c { x = 3 } else { x = 4 }\n");
+
+    let mut branches = 0;
+    let mut next = stmnts[0].clone();
+
+    loop {
+        next = match *next {
+            Ast::If(_, ref block, ref el) => {
+                assert_eq!(block.len(), 1);
+                branches += 1;
+                el.clone()
+            }
+            Ast::Block(ref block) => {
+                assert_eq!(block.len(), 1);
+                branches += 1;
+                break;
+            }
+            ref other => panic!("expected if or else, got {:?}", other),
+        };
+    }
+
+    assert_eq!(branches, 4);
+}
+
+#[test]
+fn executor_else() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+
+    for &(a, b) in &[("true", "false"), ("false", "true"), ("false", "false")] {
+        let ctx = executor::Context::new();
+        let seen = seen.clone();
+
+        ctx.set(
+            "record",
+            RustCall::new(move |args: &Args| {
+                if let types::Value::Int(i) = *args[0] {
+                    seen.borrow_mut().push(i);
+                }
+                Ok(Rc::new(types::Value::Null))
+            }),
+        );
+
+        let source = format!("if {} {{ record(1) }} else if {} {{ record(2) }} else {{ record(3) }}\n", a, b);
+        let ast = ast::read_str("test", &source).unwrap();
+        executor::run(ctx, ast).unwrap();
+    }
+
+    assert_eq!(*seen.borrow(), vec![1, 2, 3]);
+}
+
+/// `ast::read_str` rejects what `compile_str` rejects, at the same location.
+#[test]
+fn same_errors_as_compile() {
+    for source in &[
+        "x = 1\nbreak\n",
+        "loop {\n  f = () => { continue }\n}\n",
+        "if x {\n  return 1\n}\n",
+        "x = \"\\q\"\n",
+        "x = 1\ny = 9223372036854775808\n",
+    ] {
+        let from_ast = ast::read_str("virtual.rj", source).unwrap_err();
+        let from_jit = Context::new().compile_str("virtual.rj", source).unwrap_err();
+
+        match (&from_ast, &from_jit) {
+            (Error::Compile(a, msg_a), Error::Compile(b, msg_b)) => {
+                assert_eq!(a.file, "virtual.rj");
+                assert_eq!(a, b);
+                assert_eq!(msg_a, msg_b);
+            }
+            other => panic!("expected compile errors for {:?}, got {:?}", source, other),
+        }
+
+        assert_eq!(format!("{}", from_ast), format!("{}", from_jit));
+    }
+}
+
+#[test]
+fn virtual_file_name() {
+    let err = Context::new().compile_str("<stdin>", "x = 1\ny = (\n").unwrap_err();
+
+    assert_eq!(err.location().unwrap().file, "<stdin>");
+    assert!(format!("{}", err).starts_with("<stdin>:3:1: syntax error"));
+
+    let err = ast::read_str("<stdin>", "x = 1\ny = (\n").unwrap_err();
+    assert!(format!("{}", err).starts_with("<stdin>:3:1: syntax error"));
+}
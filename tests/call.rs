@@ -0,0 +1,297 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::ast::{self, Ast};
+use runjit::executor;
+use runjit::types;
+use runjit::jit::{Context, Value};
+use runjit::Error;
+use common::{int, run_in};
+
+unsafe extern "C" fn twice(val: *const Value) -> *const Value {
+    match *val {
+        Value::Float(f) => Rc::into_raw(Rc::new(Value::Float(f * 2.0))),
+        _ => std::ptr::null(),
+    }
+}
+
+fn context(source: &str) -> Box<Context> {
+    let mut ctx = Context::new();
+    ctx.add_fn("twice", twice as *mut _, 1);
+
+    run_in(ctx, source)
+}
+
+#[test]
+fn call_by_name() {
+    let mut ctx = context("add = (a, b) => { return a + b }\nnothing = () => { }\n");
+
+    assert_eq!(
+        ctx.call("add", &[Value::Float(1.0), Value::Float(2.0)]).unwrap(),
+        Value::Float(3.0)
+    );
+    assert_eq!(ctx.call("nothing", &[]).unwrap(), Value::Null);
+}
+
+#[test]
+fn call_by_value() {
+    let mut ctx = context("on_key = (evt) => { return evt }\n");
+    let handler = ctx.get("on_key").unwrap();
+    let evt = Value::string("key");
+
+    assert_eq!(ctx.call(&*handler, std::slice::from_ref(&evt)).unwrap(), evt);
+    assert_eq!(ctx.call(&*handler, &[Value::Float(1.0)]).unwrap(), Value::Float(1.0));
+}
+
+#[test]
+fn call_host_function() {
+    let mut ctx = context("x = 1\n");
+
+    assert_eq!(ctx.call("twice", &[Value::Float(4.0)]).unwrap(), Value::Float(8.0));
+    assert_eq!(ctx.call("twice", &[Value::Null]).unwrap(), Value::Null);
+}
+
+#[test]
+fn call_errors() {
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "f = (a) => { return a }\nx = 1\n").unwrap();
+
+    match ctx.call("f", &[Value::Null]) {
+        Err(Error::Call(_)) => {}
+        other => panic!("expected call error before run, got {:?}", other),
+    }
+
+    ctx.run().unwrap();
+
+    for &(name, ref args) in &[
+        ("f", vec![]),
+        ("f", vec![Value::Null, Value::Null]),
+        ("x", vec![]),
+        ("missing", vec![]),
+        ("__add", vec![Value::Null, Value::Null]),
+    ] {
+        match ctx.call(name, args) {
+            Err(Error::Call(_)) => {}
+            other => panic!("expected call error for {}, got {:?}", name, other),
+        }
+    }
+
+    assert_eq!(ctx.call("f", &[Value::Float(2.0)]).unwrap(), Value::Float(2.0));
+}
+
+#[test]
+fn calls_in_expressions() {
+    let ctx = context(
+        "add = (a, b) => { return a + b }
+        one = () => { return 1 }
+        x = add(1, 2) * add(3, 4)
+        y = add(add(1, 2), one()) + twice(1.5)
+        z = 0
+        if add(one(), one()) == 2 {
+            z = 1
+        }
+        n = 0
+        loop n < add(2, 1) {
+            n = n + one()
+        }
+        ",
+    );
+
+    assert_eq!(int(&ctx, "x"), 21);
+    assert_eq!(*ctx.get("y").unwrap(), Value::Float(7.0));
+    assert_eq!(int(&ctx, "z"), 1);
+    assert_eq!(int(&ctx, "n"), 3);
+}
+
+#[test]
+fn host_calls_in_arguments() {
+    let mut ctx = Context::new();
+    let printed = Rc::new(RefCell::new(Vec::new()));
+
+    {
+        let printed = printed.clone();
+        ctx.register("print", move |s: &str| printed.borrow_mut().push(s.to_string()));
+    }
+
+    ctx.compile_str("test", "h = () => { return \"hi\" }\nprint(h())\nf = () => { print(h()) }\nf()\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*printed.borrow(), vec!["hi", "hi"]);
+}
+
+#[test]
+fn chained_calls() {
+    let ctx = context(
+        "make = (n) => { add = (x) => { return x + n }\nreturn add }
+        a = make(1)(2)
+        curry = (a) => { f = (b) => { g = (c) => { return a * 100 + b * 10 + c }\nreturn g }\nreturn f }
+        b = curry(1)(2)(3)
+        ",
+    );
+
+    assert_eq!(int(&ctx, "a"), 3);
+    assert_eq!(int(&ctx, "b"), 123);
+}
+
+#[test]
+fn calls_on_paths() {
+    let ctx = context(
+        "handlers = [0, 0]
+        handlers[1] = (evt) => { return evt * 2 }
+        i = 1
+        a = handlers[i](21)
+        obj = {}
+        obj.on = (evt) => { return evt + 1 }
+        b = obj.on(1) + obj[\"on\"](2)
+        ",
+    );
+
+    assert_eq!(int(&ctx, "a"), 42);
+    assert_eq!(int(&ctx, "b"), 5);
+}
+
+#[test]
+fn chained_call_errors() {
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "f = () => { return 1 }\nx = f()(2)\ny = 3\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "can not call `f()`, it is int"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    assert_eq!(*ctx.get("x").unwrap(), Value::Null);
+    assert_eq!(int(&ctx, "y"), 3);
+}
+
+#[test]
+fn chained_call_arity() {
+    let mut ctx = Context::new();
+    ctx.compile_str(
+        "test",
+        "make = (n) => { return (x) => { return x + n } }\nx = make(10)(5, 6)\nhandlers = [(e) => { return e }]\ny = handlers[0]()\nz = make(1)(2)\n",
+    ).unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`make(10)` takes 1 arguments, 2 given"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    assert_eq!(*ctx.get("x").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("y").unwrap(), Value::Null);
+    assert_eq!(int(&ctx, "z"), 3);
+
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "handlers = [(e) => { return e }]\ny = handlers[0]()\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`handlers[0]` takes 1 arguments, 0 given"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn null_results() {
+    let mut ctx = Context::new();
+    ctx.add_fn("twice", twice as *mut _, 1);
+    ctx.compile_str(
+        "test",
+        "r = twice(1)\ns = [twice(1)]\nf = () => { return [twice(1), twice(1) == null] }\nu = f()\nt = twice(1) + 1\n",
+    ).unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "can not apply `+` to null and int"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    assert_eq!(*ctx.get("r").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("s").unwrap(), Value::array(vec![Value::Null]));
+    assert_eq!(*ctx.get("u").unwrap(), Value::array(vec![Value::Null, Value::Bool(true)]));
+    assert_eq!(*ctx.get("t").unwrap(), Value::Null);
+}
+
+#[test]
+fn unknown_lambdas() {
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "r = __global_get(1)\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`__global_get` is not defined"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "f = () => { return __global_get(1, 2) }\nr = f()\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`__global_get` is not defined"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    let mut ctx = Context::new();
+    ctx.set("f", Value::Lambda(0x10));
+    ctx.compile_str("test", "r = f(1)\ns = 2\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "can not call `f`, no lambda at 0x10"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    assert_eq!(*ctx.get("r").unwrap(), Value::Null);
+    assert_eq!(int(&ctx, "s"), 2);
+}
+
+#[test]
+fn executor_call_arity() {
+    let ctx = executor::Context::new();
+    ctx.set("f", Rc::new(types::Value::Lambda(vec![String::from("a")], vec![])));
+
+    let ast = ast::read_str("test", "f()\n").unwrap();
+    match executor::run(ctx, ast) {
+        Err(ref msg) => assert_eq!(msg, "lambda takes 1 arguments, 0 given"),
+        Ok(_) => panic!("expected an error"),
+    }
+}
+
+#[test]
+fn calls_in_the_ast() {
+    let ast = ast::read_str("test", "x = f(1)(2) + g()\n").unwrap();
+
+    let exp = match *ast {
+        Ast::Block(ref stmnts) => match *stmnts[0] {
+            Ast::Assign(_, ref exp) => exp.clone(),
+            ref other => panic!("expected assign, got {:?}", other),
+        },
+        ref other => panic!("expected block, got {:?}", other),
+    };
+
+    match *exp {
+        Ast::Exp(_, ref left, ref right) => {
+            match **left {
+                Ast::Call(ref callee, ref args) => {
+                    assert_eq!(args.len(), 1);
+                    match **callee {
+                        Ast::Call(ref callee, ref args) => {
+                            assert_eq!(args.len(), 1);
+                            match **callee {
+                                Ast::Var(_) => {}
+                                ref other => panic!("expected var, got {:?}", other),
+                            }
+                        }
+                        ref other => panic!("expected call, got {:?}", other),
+                    }
+                }
+                ref other => panic!("expected call, got {:?}", other),
+            }
+            match **right {
+                Ast::Call(_, ref args) => assert!(args.is_empty()),
+                ref other => panic!("expected call, got {:?}", other),
+            }
+        }
+        ref other => panic!("expected exp, got {:?}", other),
+    }
+}
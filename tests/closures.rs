@@ -0,0 +1,84 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use runjit::Error;
+use common::run;
+
+#[test]
+fn make_adder() {
+    let mut ctx = run("make = (n) => { add = (x) => { return x + n }\nreturn add }\n");
+
+    let add5 = ctx.call("make", (5,)).unwrap();
+    let add7 = ctx.call("make", (7,)).unwrap();
+
+    assert_eq!(ctx.call(&add5, (1,)).unwrap(), Value::Int(6));
+    assert_eq!(ctx.call(&add7, (1,)).unwrap(), Value::Int(8));
+    assert_eq!(ctx.call(&add5, (2,)).unwrap(), Value::Int(7));
+    assert_eq!(add5.type_name(), "lambda");
+}
+
+#[test]
+fn nested_captures() {
+    let mut ctx = run(
+        "outer = (a) => { mid = (b) => { inner = (c) => { return a * 100 + b * 10 + c }\nreturn inner }\nreturn mid }\n",
+    );
+
+    let mid = ctx.call("outer", (1,)).unwrap();
+    let inner = ctx.call(&mid, (2,)).unwrap();
+
+    assert_eq!(ctx.call(&inner, (3,)).unwrap(), Value::Int(123));
+}
+
+#[test]
+fn called_from_scripts() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = Context::new();
+
+    {
+        let seen = seen.clone();
+        ctx.register("record", move |v: i64| seen.borrow_mut().push(v));
+    }
+
+    ctx.compile_str(
+        "test",
+        "make = (n, out) => { out.add = (x) => { record(x + n) } }\nfns = {}\nmake(10, fns)\nfns.add(1)\nmake(20, fns)\nfns.add(2)\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*seen.borrow(), vec![11, 22]);
+}
+
+#[test]
+fn captured_by_value() {
+    let mut ctx = run(
+        "make = (d) => { n = 1\nget = () => { return n * 10 + d.x }\nn = 2\nd.x = 3\nreturn get }\nd = {x: 0}\n",
+    );
+
+    let get = ctx.call("make", &[(*ctx.get("d").unwrap()).clone()]).unwrap();
+
+    // `n` was copied when the closure was created, the dict is shared
+    assert_eq!(ctx.call(&get, ()).unwrap(), Value::Int(13));
+}
+
+#[test]
+fn wrong_arity() {
+    let mut ctx = Context::new();
+    ctx.compile_str(
+        "test",
+        "f = (a, b) => { return b }\nr = f(1)\nmake = (n) => { return (x) => { return x + n } }\nadd = make(1)\ns = add(1, 2)\nt = add(2)\n",
+    ).unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`f` takes 2 arguments, 1 given"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+
+    assert_eq!(*ctx.get("r").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("s").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("t").unwrap(), Value::Int(3));
+}
@@ -0,0 +1,76 @@
+//! Helpers shared by the tests, not every test uses all of them.
+#![allow(dead_code)]
+
+use runjit::ast::{self, Ast};
+use runjit::jit::{Context, Value};
+use runjit::Error;
+
+/// Compile and run `source` in a new context.
+pub fn run(source: &str) -> Box<Context> {
+    run_in(Context::new(), source)
+}
+
+/// Compile and run `source` in `ctx`, which can be prepared with globals or functions.
+pub fn run_in(mut ctx: Box<Context>, source: &str) -> Box<Context> {
+    ctx.compile_str("test", source).unwrap();
+    ctx.run().unwrap();
+
+    ctx
+}
+
+/// The global `name`, which has to be an int.
+pub fn int(ctx: &Context, name: &str) -> i64 {
+    match *ctx.get(name).unwrap() {
+        Value::Int(i) => i,
+        ref other => panic!("expected int for {}, got {:?}", name, other),
+    }
+}
+
+/// The value of the expression `source`.
+pub fn eval(source: &str) -> Value {
+    eval_in(Context::new(), source)
+}
+
+/// The value of the expression `source` in `ctx`.
+pub fn eval_in(ctx: Box<Context>, source: &str) -> Value {
+    let ctx = run_in(ctx, &format!("x = {}\n", source));
+    let x = ctx.get("x").unwrap();
+
+    (*x).clone()
+}
+
+/// The message of the runtime error `source` fails with.
+pub fn runtime_error(source: &str) -> String {
+    runtime_error_in(Context::new(), source)
+}
+
+/// The message of the runtime error `source` fails with in `ctx`.
+pub fn runtime_error_in(mut ctx: Box<Context>, source: &str) -> String {
+    ctx.compile_str("test", source).unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(msg)) => msg,
+        other => panic!("expected a runtime error for {}, got {:?}", source, other),
+    }
+}
+
+/// The AST of the expression `source`, like `(Add 1 (Mul 2 3))`.
+pub fn tree(source: &str) -> String {
+    fn render(ast: &Ast) -> String {
+        match *ast {
+            Ast::Exp(ref op, ref left, ref right) => format!("({:?} {} {})", op, render(left), render(right)),
+            Ast::Unary(ref op, ref operand) => format!("({:?} {})", op, render(operand)),
+            Ast::Int(i) => format!("{}", i),
+            Ast::Var(_) => String::from("var"),
+            ref other => panic!("unexpected ast: {:?}", other),
+        }
+    }
+
+    match *ast::read_str("test", &format!("x = {}\n", source)).unwrap() {
+        Ast::Block(ref stmnts) => match *stmnts[0] {
+            Ast::Assign(_, ref exp) => render(exp),
+            ref other => panic!("expected assign, got {:?}", other),
+        },
+        ref other => panic!("expected block, got {:?}", other),
+    }
+}
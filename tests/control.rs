@@ -0,0 +1,178 @@
+extern crate runjit;
+
+mod common;
+
+use runjit::jit::{Context, Value};
+use common::{int, run};
+
+#[test]
+fn if_else_chains() {
+    let source = "r = 0\n\
+                  if n == 1 { r = 1 } else if n == 2 { r = 2 } else if n == 3 { r = 3 } else if n == 4 { r = 4 } else { r = 5 }\n";
+
+    for n in 1..7 {
+        let mut ctx = Context::new();
+        ctx.set("n", n);
+        ctx.compile_str("test", source).unwrap();
+        ctx.run().unwrap();
+
+        assert_eq!(int(&ctx, "r"), n.min(5));
+    }
+}
+
+#[test]
+fn branches_fall_through() {
+    // without an else nothing runs, and every branch continues after the chain
+    let ctx = run(
+        "a = 0\nif false { a = 1 } else if false { a = 2 } else if false { a = 3 }\nafter_a = 1\n\
+         b = 0\nif false { b = 1 } else if true { b = 2 } else if true { b = 3 }\nafter_b = 1\n\
+         c = 0\nif true { } else { c = 1 }\nafter_c = 1\n",
+    );
+
+    assert_eq!(int(&ctx, "a"), 0);
+    assert_eq!(int(&ctx, "b"), 2);
+    assert_eq!(int(&ctx, "c"), 0);
+    assert_eq!(int(&ctx, "after_a") + int(&ctx, "after_b") + int(&ctx, "after_c"), 3);
+}
+
+#[test]
+fn nested_ifs_in_lambdas() {
+    let ctx = run(
+        "sign = (n) => {\n\
+           if n < 0 { return -1 } else if n == 0 { return 0 }\n\
+           if n > 100 {\n\
+             if n > 1000 { return 3 } else { return 2 }\n\
+           }\n\
+           return 1\n\
+         }\n\
+         a = sign(-5)\nb = sign(0)\nc = sign(5)\nd = sign(500)\ne = sign(5000)\n",
+    );
+
+    let results: Vec<i64> = ["a", "b", "c", "d", "e"].iter().map(|n| int(&ctx, n)).collect();
+    assert_eq!(results, vec![-1, 0, 1, 2, 3]);
+}
+
+#[test]
+fn while_loops() {
+    let ctx = run("n = 0\nsum = 0\nloop n < 10 { n = n + 1\nsum = sum + n }\nnever = 0\nloop false { never = 1 }\n");
+
+    assert_eq!(int(&ctx, "n"), 10);
+    assert_eq!(int(&ctx, "sum"), 55);
+    assert_eq!(int(&ctx, "never"), 0);
+}
+
+#[test]
+fn infinite_loops() {
+    let ctx = run("n = 0\nloop { n = n + 1\nif n == 7 { break } }\nafter = n\n");
+
+    assert_eq!(int(&ctx, "n"), 7);
+    assert_eq!(int(&ctx, "after"), 7);
+}
+
+#[test]
+fn break_and_continue() {
+    // sum of the odd numbers below 10, stopping at 7
+    let ctx = run(
+        "n = 0\nsum = 0\n\
+         loop n < 10 {\n\
+           n = n + 1\n\
+           if n % 2 == 0 { continue }\n\
+           if n > 7 { break }\n\
+           sum = sum + n\n\
+         }\n",
+    );
+
+    assert_eq!(int(&ctx, "n"), 9);
+    assert_eq!(int(&ctx, "sum"), 1 + 3 + 5 + 7);
+}
+
+#[test]
+fn nested_loops_in_lambdas() {
+    // `break` and `continue` only affect the innermost loop
+    let ctx = run(
+        "pairs = (n) => {\n\
+           count = 0\n\
+           i = 0\n\
+           loop i < n {\n\
+             i = i + 1\n\
+             j = 0\n\
+             loop {\n\
+               j = j + 1\n\
+               if j > i { break }\n\
+               if j == 2 { continue }\n\
+               count = count + 1\n\
+             }\n\
+             if i == 3 { continue }\n\
+           }\n\
+           return count\n\
+         }\n\
+         a = pairs(4)\n\
+         outer = 0\n\
+         loop outer < 2 { outer = outer + 1\nb = pairs(outer) }\n",
+    );
+
+    // j runs 1..=i without 2: 1 + 1 + 2 + 3
+    assert_eq!(int(&ctx, "a"), 7);
+    assert_eq!(int(&ctx, "b"), 2);
+    assert_eq!(int(&ctx, "outer"), 2);
+}
+
+#[test]
+fn loops_do_not_reach_into_lambdas() {
+    match Context::new().compile_str("test", "loop {\n  f = () => { break }\n  break\n}\n") {
+        Err(runjit::Error::Compile(ref loc, ref msg)) => {
+            assert_eq!((loc.line, loc.column), (2, 15));
+            assert_eq!(msg, "`break` outside of a loop");
+        }
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
+
+#[test]
+fn early_returns() {
+    let ctx = run(
+        "find = (items, wanted) => {\n\
+           i = 0\n\
+           loop i < 10 {\n\
+             if items[i] == wanted { return i }\n\
+             i = i + 1\n\
+           }\n\
+           return -1\n\
+         }\n\
+         first = (n) => { if n > 0 { return 1 } else { return 2 }\nnever = 1 }\n\
+         items = [5, 6, 7, 8, 9, 10, 11, 12, 13, 14]\n\
+         a = find(items, 7)\nb = find(items, 99)\nc = first(1)\nd = first(-1)\n",
+    );
+
+    assert_eq!(int(&ctx, "a"), 2);
+    assert_eq!(int(&ctx, "b"), -1);
+    assert_eq!(int(&ctx, "c"), 1);
+    assert_eq!(int(&ctx, "d"), 2);
+    assert_eq!(ctx.get("never"), None);
+}
+
+#[test]
+fn bare_returns() {
+    let ctx = run(
+        "f = () => { return }\ng = (n) => { if n { return }\nreturn 1 }\nh = () => { x = 1 }\n\
+         a = f()\nb = g(true)\nc = g(false)\nd = h()\n",
+    );
+
+    assert_eq!(*ctx.get("a").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("b").unwrap(), Value::Null);
+    assert_eq!(int(&ctx, "c"), 1);
+    assert_eq!(*ctx.get("d").unwrap(), Value::Null);
+}
+
+#[test]
+fn return_outside_of_a_lambda() {
+    for &(source, line, column) in &[("return 1\n", 1, 1), ("x = 1\nif x {\n  return\n}\n", 3, 3), ("loop { return }\n", 1, 8)] {
+        match Context::new().compile_str("test", source) {
+            Err(runjit::Error::Compile(ref loc, ref msg)) => {
+                assert_eq!((loc.line, loc.column), (line, column));
+                assert_eq!(msg, "`return` outside of a lambda");
+            }
+            other => panic!("expected a compile error for {:?}, got {:?}", source, other),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+extern crate runjit;
+
+use std::collections::{BTreeMap, HashMap};
+
+use runjit::jit::{Context, FromValue, IntoValue, Value};
+use runjit::Error;
+
+fn type_error<T: FromValue + std::fmt::Debug>(val: Value) -> String {
+    match T::from_value(&val) {
+        Err(Error::Type(msg)) => msg,
+        other => panic!("expected a type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn scalars() {
+    assert_eq!(f64::from_value(&Value::Float(1.5)).unwrap(), 1.5);
+    assert_eq!(i32::from_value(&Value::Float(-3.0)).unwrap(), -3);
+    assert_eq!(u8::from_value(&Value::Float(255.0)).unwrap(), 255);
+    assert_eq!(String::from_value(&Value::string("abc")).unwrap(), "abc");
+    assert!(bool::from_value(&Value::Bool(true)).unwrap());
+    assert!(!bool::from_value(&Value::Bool(false)).unwrap());
+    assert_eq!(Option::<f64>::from_value(&Value::Null).unwrap(), None);
+    assert_eq!(Option::<f64>::from_value(&Value::Float(2.0)).unwrap(), Some(2.0));
+    assert_eq!(<()>::from_value(&Value::Null).unwrap(), ());
+
+    assert_eq!(7u32.into_value(), Value::Int(7));
+    assert_eq!("abc".into_value(), Value::string("abc"));
+    assert_eq!(true.into_value(), Value::Bool(true));
+    assert_eq!(false.into_value(), Value::Bool(false));
+    assert_eq!(None::<f64>.into_value(), Value::Null);
+    assert_eq!(().into_value(), Value::Null);
+}
+
+#[test]
+fn collections() {
+    let list = vec![1.0, 2.0].into_value();
+    assert_eq!(list, Value::array(vec![Value::Float(1.0), Value::Float(2.0)]));
+    assert_eq!(Vec::<i64>::from_value(&list).unwrap(), vec![1, 2]);
+
+    let mut map = BTreeMap::new();
+    map.insert(String::from("name"), String::from("runjit"));
+    let dict = map.clone().into_value();
+    assert_eq!(dict, Value::dict(vec![("name", Value::string("runjit"))]));
+    assert_eq!(BTreeMap::<String, String>::from_value(&dict).unwrap(), map);
+    assert_eq!(
+        HashMap::<String, String>::from_value(&dict).unwrap()["name"],
+        "runjit"
+    );
+}
+
+#[test]
+fn mismatches() {
+    assert_eq!(type_error::<f64>(Value::string("a")), "expected float, got string");
+    assert_eq!(type_error::<String>(Value::Null), "expected string, got null");
+    assert_eq!(type_error::<()>(Value::Float(1.0)), "expected null, got float");
+    assert_eq!(type_error::<bool>(Value::Float(1.0)), "expected bool, got float");
+    assert_eq!(type_error::<u8>(Value::Float(-1.0)), "expected u8, got float -1 which does not fit");
+    assert_eq!(type_error::<i32>(Value::Float(1.5)), "expected i32, got float 1.5 which does not fit");
+    assert_eq!(type_error::<Vec<f64>>(Value::Float(1.0)), "expected array, got float");
+    assert_eq!(
+        type_error::<Vec<f64>>(Value::array(vec![Value::Float(1.0), Value::Null])),
+        "expected float, got null at [1]"
+    );
+    assert_eq!(
+        type_error::<BTreeMap<String, f64>>(Value::dict(vec![("size", Value::string("big"))])),
+        "expected float, got string at key `size`"
+    );
+}
+
+#[test]
+fn host_functions_and_calls() {
+    let mut ctx = Context::new();
+
+    ctx.register("total", |list: Vec<f64>| list.iter().sum::<f64>());
+    ctx.register("greet", |name: Option<String>| {
+        format!("hello {}", name.unwrap_or_else(|| String::from("you")))
+    });
+    ctx.set("list", vec![1, 2, 3]);
+    ctx.compile_str("test", "add = (a, b) => { return a + b }\n").unwrap();
+    ctx.run().unwrap();
+
+    let list = ctx.get("list").unwrap();
+    assert_eq!(ctx.call_as::<f64, _, _>("total", &[(*list).clone()]).unwrap(), 6.0);
+    assert_eq!(ctx.call_as::<f64, _, _>("total", (vec![0.5, 0.5],)).unwrap(), 1.0);
+    assert_eq!(ctx.call_as::<u32, _, _>("add", (1, 2)).unwrap(), 3);
+    assert_eq!(ctx.call_as::<String, _, _>("greet", (None::<String>,)).unwrap(), "hello you");
+    assert_eq!(ctx.call("greet", ("me",)).unwrap(), Value::string("hello me"));
+
+    match ctx.call_as::<String, _, _>("add", (1, 2)) {
+        Err(Error::Type(ref msg)) => assert_eq!(msg, "expected string, got int"),
+        other => panic!("expected a type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn float_bounds() {
+    let two_63 = 9223372036854775808.0;
+    let two_64 = 18446744073709551616.0;
+
+    assert_eq!(i64::from_value(&Value::Float(-two_63)).unwrap(), i64::MIN);
+    assert_eq!(i64::from_value(&Value::Float(9223372036854774784.0)).unwrap(), 9223372036854774784);
+    assert_eq!(type_error::<i64>(Value::Float(two_63)), "expected i64, got float 9223372036854776000 which does not fit");
+    assert_eq!(type_error::<isize>(Value::Float(two_63)), "expected isize, got float 9223372036854776000 which does not fit");
+    assert_eq!(u64::from_value(&Value::Float(two_63)).unwrap(), 1 << 63);
+    assert_eq!(type_error::<u64>(Value::Float(two_64)), "expected u64, got float 18446744073709552000 which does not fit");
+    assert_eq!(type_error::<usize>(Value::Float(two_64)), "expected usize, got float 18446744073709552000 which does not fit");
+    assert_eq!(i32::from_value(&Value::Float(2147483647.0)).unwrap(), i32::MAX);
+    assert_eq!(type_error::<i32>(Value::Float(2147483648.0)), "expected i32, got float 2147483648 which does not fit");
+    assert_eq!(type_error::<u8>(Value::Float(256.0)), "expected u8, got float 256 which does not fit");
+}
@@ -0,0 +1,72 @@
+extern crate runjit;
+
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use runjit::Error;
+
+unsafe extern "C" fn twice(val: *const Value) -> *const Value {
+    match *val {
+        Value::Float(f) => Rc::into_raw(Rc::new(Value::Float(f * 2.0))),
+        _ => std::ptr::null(),
+    }
+}
+
+#[test]
+fn syntax_location() {
+    let err = Context::new().compile_str("errors.rj", "x = 1\ny = 2 + * 3\n").unwrap_err();
+
+    match err {
+        Error::Syntax(ref loc, _) => {
+            assert_eq!(loc.file, "errors.rj");
+            assert_eq!((loc.line, loc.column), (2, 9));
+            assert_eq!(loc.snippet, "y = 2 + * 3");
+        }
+        ref other => panic!("expected a syntax error, got {:?}", other),
+    }
+
+    assert!(format!("{}", err).starts_with("errors.rj:2:9: syntax error: "));
+}
+
+#[test]
+fn compile_location() {
+    let err = Context::new().compile_str("errors.rj", "x = 1\nif x {\n  break\n}\n").unwrap_err();
+
+    match err {
+        Error::Compile(ref loc, ref msg) => {
+            assert_eq!(loc.file, "errors.rj");
+            assert_eq!((loc.line, loc.column), (3, 3));
+            assert_eq!(loc.snippet, "  break");
+            assert_eq!(msg, "`break` outside of a loop");
+        }
+        ref other => panic!("expected a compile error, got {:?}", other),
+    }
+
+    assert_eq!(
+        format!("{}", err),
+        "errors.rj:3:3: `break` outside of a loop\n  |   break\n  |   ^"
+    );
+}
+
+#[test]
+fn native_arity() {
+    let mut ctx = Context::new();
+    ctx.add_fn("twice", twice as *mut _, 1);
+
+    let err = ctx.compile_str("errors.rj", "f = () => {\n  return twice(1, 2)\n}\n").unwrap_err();
+
+    match err {
+        Error::Compile(ref loc, ref msg) => {
+            assert_eq!((loc.line, loc.column), (2, 10));
+            assert_eq!(msg, "`twice` takes 1 arguments, 2 given");
+        }
+        ref other => panic!("expected a compile error, got {:?}", other),
+    }
+
+    // the failed script is discarded, the context stays usable
+    ctx.compile_str("errors.rj", "x = 1\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("x").unwrap(), Value::Int(1));
+    assert_eq!(ctx.get("f"), None);
+}
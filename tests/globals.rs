@@ -0,0 +1,152 @@
+extern crate runjit;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use runjit::Error;
+
+fn float(ctx: &Context, name: &str) -> f64 {
+    match *ctx.get(name).unwrap() {
+        Value::Float(f) => f,
+        ref other => panic!("expected float for {}, got {:?}", name, other),
+    }
+}
+
+#[test]
+fn set_before_compile() {
+    let mut ctx = Context::new();
+
+    ctx.set("x", Value::Float(2.0));
+    ctx.compile_str("test", "y = x * 3\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(float(&ctx, "y"), 6.0);
+}
+
+#[test]
+fn set_between_runs() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str("test", "y = x * 3\n").unwrap();
+    ctx.set("x", Value::Float(1.0));
+    ctx.run().unwrap();
+    assert_eq!(float(&ctx, "y"), 3.0);
+
+    // `run` only executes new scripts
+    ctx.set("x", Value::Float(5.0));
+    ctx.run().unwrap();
+    assert_eq!(float(&ctx, "y"), 3.0);
+
+    ctx.run_all().unwrap();
+    assert_eq!(float(&ctx, "y"), 15.0);
+}
+
+#[test]
+fn compile_between_runs() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str("first", "f = (a) => { return a * 2 }\nx = 1\n").unwrap();
+    ctx.run().unwrap();
+    let f = ctx.get("f").unwrap();
+
+    ctx.compile_str("second", "y = x + 1\n").unwrap();
+    ctx.set("x", Value::Float(10.0));
+    ctx.run().unwrap();
+
+    assert_eq!(float(&ctx, "x"), 10.0);
+    assert_eq!(float(&ctx, "y"), 11.0);
+    assert_eq!(ctx.call(&*f, &[Value::Float(4.0)]).unwrap(), Value::Float(8.0));
+    assert_eq!(ctx.call("f", &[Value::Float(5.0)]).unwrap(), Value::Float(10.0));
+}
+
+#[test]
+fn run_executes_new_scripts() {
+    let hits = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = Context::new();
+
+    {
+        let hits = hits.clone();
+        ctx.register("hit", move |n: f64| hits.borrow_mut().push(n));
+    }
+
+    ctx.compile_str("first", "hit(1)\n").unwrap();
+    ctx.run().unwrap();
+    ctx.compile_str("second", "hit(2)\n").unwrap();
+    ctx.run().unwrap();
+    ctx.run().unwrap();
+    assert_eq!(*hits.borrow(), vec![1.0, 2.0]);
+
+    ctx.run_all().unwrap();
+    assert_eq!(*hits.borrow(), vec![1.0, 2.0, 1.0, 2.0]);
+}
+
+#[test]
+fn functions_keep_script_globals() {
+    let mut ctx = Context::new();
+    ctx.register("hit", |_: f64| ());
+
+    ctx.compile_str("first", "hit = 5\n").unwrap();
+    ctx.run().unwrap();
+    ctx.compile_str("second", "y = 1\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("hit").unwrap(), Value::Int(5));
+}
+
+#[test]
+fn missing_global_is_an_error() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str("test", "y = x\nz = 1\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`x` is not defined"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    assert_eq!(*ctx.get("y").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("z").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn null_is_not_missing() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str("test", "x = null\ny = 1\ny = null\nf = (a) => { return a == null }\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(ctx.get("x"), Some(Rc::new(Value::Null)));
+    assert_eq!(ctx.get("y"), Some(Rc::new(Value::Null)));
+    assert_eq!(ctx.get("missing"), None);
+    assert_eq!(ctx.call("f", &[Value::Null]).unwrap(), Value::Bool(true));
+    assert_eq!(ctx.call("f", &[Value::Int(0)]).unwrap(), Value::Bool(false));
+}
+
+#[test]
+fn collections() {
+    let mut ctx = Context::new();
+
+    ctx.set("list", Value::array(vec![Value::Float(1.0), Value::string("two")]));
+    ctx.set(
+        "config",
+        Value::dict(vec![("name", Value::string("runjit")), ("size", Value::Float(3.0))]),
+    );
+    ctx.compile_str("test", "a = list\nc = config\n").unwrap();
+    ctx.run().unwrap();
+
+    match *ctx.get("a").unwrap() {
+        Value::Array(ref a) => {
+            assert_eq!(*a.borrow()[0], Value::Float(1.0));
+            assert_eq!(*a.borrow()[1], Value::string("two"));
+        }
+        ref other => panic!("expected array, got {:?}", other),
+    }
+
+    match *ctx.get("c").unwrap() {
+        Value::Dict(ref d) => {
+            assert_eq!(*d.borrow()["name"], Value::string("runjit"));
+            assert_eq!(*d.borrow()["size"], Value::Float(3.0));
+        }
+        ref other => panic!("expected dict, got {:?}", other),
+    }
+}
@@ -0,0 +1,160 @@
+extern crate runjit;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use runjit::jit::{Context, Value};
+use runjit::types::{self, Args, RustCall};
+use runjit::Error;
+
+#[test]
+fn typed_parameters() {
+    let mut ctx = Context::new();
+
+    ctx.register("repeat", |s: &str, n: f64| -> Result<Value, Error> {
+        Ok(Value::string(s.repeat(n as usize)))
+    });
+    ctx.register("sum", |a: f64, b: f64, c: f64| -> Result<f64, Error> { Ok(a + b + c) });
+    ctx.register("now", || -> Result<f64, Error> { Ok(42.0) });
+
+    ctx.compile_str("test", "x = 1\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(
+        ctx.call("repeat", &[Value::string("ab"), Value::Float(3.0)]).unwrap(),
+        Value::string("ababab")
+    );
+    assert_eq!(
+        ctx.call("sum", &[Value::Float(1.0), Value::Float(2.0), Value::Float(3.0)]).unwrap(),
+        Value::Float(6.0)
+    );
+    assert_eq!(ctx.call("now", &[]).unwrap(), Value::Float(42.0));
+}
+
+#[test]
+fn called_from_script() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = Context::new();
+
+    {
+        let seen = seen.clone();
+        ctx.register("log", move |s: &str, val: &Value| -> Result<(), Error> {
+            seen.borrow_mut().push(format!("{} {:?}", s, val));
+            Ok(())
+        });
+    }
+
+    ctx.compile_str(
+        "test",
+        "log(\"top\", 1)\nf = (a) => { log(\"lambda\", a + 1) }\nf(2)\nkeep = log\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    let keep = ctx.get("keep").unwrap();
+    ctx.call(&*keep, &[Value::string("call"), Value::Null]).unwrap();
+
+    assert_eq!(
+        *seen.borrow(),
+        vec![
+            String::from("top Int(1)"),
+            String::from("lambda Int(3)"),
+            String::from("call Null"),
+        ]
+    );
+}
+
+#[test]
+fn wrong_arguments() {
+    let calls = Rc::new(RefCell::new(0));
+    let mut ctx = Context::new();
+
+    {
+        let calls = calls.clone();
+        ctx.register("count", move |_: f64| -> Result<(), Error> {
+            *calls.borrow_mut() += 1;
+            Ok(())
+        });
+    }
+
+    for source in &["count(1, 2)\n", "count()\n", "count(\"one\")\n"] {
+        let mut ctx = Context::new();
+        ctx.register("count", |_: f64| -> Result<(), Error> { Ok(()) });
+        ctx.compile_str("test", source).unwrap();
+
+        match ctx.run() {
+            Err(Error::Call(_)) | Err(Error::Type(_)) => {}
+            other => panic!("expected an error for {}, got {:?}", source, other),
+        }
+    }
+
+    ctx.compile_str("test", "count(\"one\")\nx = 1\ncount(2)\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Type(ref msg)) => assert_eq!(msg, "expected float, got string"),
+        other => panic!("expected a type error, got {:?}", other),
+    }
+
+    // the script goes on after an error
+    assert_eq!(*ctx.get("x").unwrap(), Value::Int(1));
+    assert_eq!(*calls.borrow(), 1);
+
+    match ctx.call("count", &[Value::Null]) {
+        Err(Error::Type(_)) => {}
+        other => panic!("expected a type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn host_error() {
+    let mut ctx = Context::new();
+
+    ctx.register("fail", |msg: String| -> Result<(), Error> { Err(Error::Call(msg)) });
+    ctx.compile_str("test", "fail(\"first\")\nx = 1\nfail(\"second\")\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Call(ref msg)) => assert_eq!(msg, "first"),
+        other => panic!("expected the host error, got {:?}", other),
+    }
+
+    assert_eq!(*ctx.get("x").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn rust_call_closure() {
+    let (tx, rx) = mpsc::channel();
+    let mut ctx = Context::new();
+
+    ctx.add_call(
+        "send",
+        RustCall::new(move |args: &Args| {
+            let line: Vec<String> = args
+                .iter()
+                .map(|a| match **a {
+                    types::Value::Int(i) => i.to_string(),
+                    types::Value::Float(f) => f.to_string(),
+                    types::Value::String(ref s) => s.clone(),
+                    _ => String::from("?"),
+                })
+                .collect();
+
+            tx.send(line.join(" ")).map_err(|e| e.to_string())?;
+            Ok(Rc::new(types::Value::Null))
+        }),
+    ).unwrap();
+    ctx.add_call("fail", RustCall::new(|_: &Args| Err(String::from("closed")))).unwrap();
+
+    assert!(ctx.add_call("float", Rc::new(types::Value::Float(1.0))).is_err());
+
+    ctx.compile_str("test", "send(\"a\", 1)\nsend()\nf = (x) => { send(x, x * 2, \"!\") }\nf(3)\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["a 1", "", "3 6 !"]);
+
+    ctx.compile_str("fail", "fail()\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Call(ref msg)) => assert_eq!(msg, "closed"),
+        other => panic!("expected the closure error, got {:?}", other),
+    }
+}
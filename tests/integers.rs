@@ -0,0 +1,108 @@
+extern crate runjit;
+
+mod common;
+
+use runjit::jit::{Context, FromValue, IntoValue, Value};
+use runjit::Error;
+use common::{eval, runtime_error};
+
+#[test]
+fn literals() {
+    assert_eq!(eval("0"), Value::Int(0));
+    assert_eq!(eval("42"), Value::Int(42));
+    assert_eq!(eval("9223372036854775807"), Value::Int(i64::MAX));
+    assert_eq!(eval("42.0"), Value::Float(42.0));
+    assert_eq!(eval("1."), Value::Float(1.0));
+
+    match Context::new().compile_str("test", "x = 9223372036854775808\n") {
+        Err(Error::Compile(_, ref msg)) => assert_eq!(msg, "integer literal 9223372036854775808 is too large"),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(eval("2 + 3 * 4"), Value::Int(14));
+    assert_eq!(eval("3 - 5"), Value::Int(-2));
+    assert_eq!(eval("7 / 2"), Value::Int(3));
+    assert_eq!(eval("(0 - 7) / 2"), Value::Int(-3));
+    assert_eq!(eval("(0 - 7) % 3"), Value::Int(-1));
+    assert_eq!(eval("7.0 / 2"), Value::Float(3.5));
+    assert_eq!(eval("1 + 0.5"), Value::Float(1.5));
+    assert_eq!(eval("2.5 * 2"), Value::Float(5.0));
+}
+
+#[test]
+fn comparisons() {
+    assert_eq!(eval("1 == 1.0"), Value::Bool(true));
+    assert_eq!(eval("1 != 1.5"), Value::Bool(true));
+    assert_eq!(eval("1 < 1.5"), Value::Bool(true));
+    assert_eq!(eval("2.5 >= 2"), Value::Bool(true));
+    assert_eq!(eval("9007199254740993 > 9007199254740992"), Value::Bool(true));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        runtime_error("x = 9223372036854775807 + 1\n"),
+        "integer overflow in `9223372036854775807 + 1`"
+    );
+    assert_eq!(runtime_error("x = 1 / 0\n"), "division by zero in `1 / 0`");
+    assert_eq!(runtime_error("x = 1 % 0\n"), "division by zero in `1 % 0`");
+    assert_eq!(eval("1 / 0.0"), Value::Float(f64::INFINITY));
+
+    // the script goes on with `Null`
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "x = 4611686018427387904 * 2\ny = 1\n").unwrap();
+    assert!(ctx.run().is_err());
+    assert_eq!(*ctx.get("x").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("y").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn type_errors() {
+    assert_eq!(runtime_error("x = \"a\" * 2\n"), "can not apply `*` to string and int");
+    assert_eq!(runtime_error("x = [] - 1\n"), "can not apply `-` to array and int");
+    assert_eq!(runtime_error("x = null + 1\n"), "can not apply `+` to null and int");
+    assert_eq!(runtime_error("x = 1.5 / {}\n"), "can not apply `/` to float and dict");
+    assert_eq!(runtime_error("x = \"7\" % 2\n"), "can not apply `%` to string and int");
+    assert_eq!(runtime_error("x = true + false\n"), "can not apply `+` to bool and bool");
+
+    // the script goes on with `Null`
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "x = \"a\" + 1\ny = 1\n").unwrap();
+    assert!(ctx.run().is_err());
+    assert_eq!(*ctx.get("x").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("y").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn indices() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str("test", "list = [10, 20, 30]\ni = 1\na = list[i + 1]\nb = list[1.0]\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("a").unwrap(), Value::Int(30));
+    assert_eq!(*ctx.get("b").unwrap(), Value::Int(20));
+
+    assert_eq!(
+        runtime_error("list = [1]\nx = list[0 - 1]\n"),
+        "invalid index -1 in `list[-1]`"
+    );
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(i64::from_value(&Value::Int(-5)).unwrap(), -5);
+    assert_eq!(u8::from_value(&Value::Int(255)).unwrap(), 255);
+    assert_eq!(f64::from_value(&Value::Int(2)).unwrap(), 2.0);
+
+    match u8::from_value(&Value::Int(300)) {
+        Err(Error::Type(ref msg)) => assert_eq!(msg, "expected u8, got int 300 which does not fit"),
+        other => panic!("expected a type error, got {:?}", other),
+    }
+
+    assert_eq!(5i32.into_value(), Value::Int(5));
+    assert_eq!(u64::MAX.into_value(), Value::Float(u64::MAX as f64));
+}
@@ -0,0 +1,130 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::ast::{self, Ast};
+use runjit::jit::{Context, Value};
+use common::run;
+
+fn get(ctx: &Context, name: &str) -> Value {
+    (*ctx.get(name).unwrap()).clone()
+}
+
+#[test]
+fn nested() {
+    let ctx = run("d = { a: [1, { b: 2 }], c: {}, e: [[], [3]] }\nx = d.a[1].b\n");
+
+    assert_eq!(
+        get(&ctx, "d"),
+        Value::dict(vec![
+            ("a", Value::array(vec![Value::Int(1), Value::dict(vec![("b", Value::Int(2))])])),
+            ("c", Value::dict(Vec::<(&str, Value)>::new())),
+            ("e", Value::array(vec![Value::array(vec![]), Value::array(vec![Value::Int(3)])])),
+        ])
+    );
+    assert_eq!(get(&ctx, "x"), Value::Int(2));
+}
+
+#[test]
+fn arguments() {
+    let sums = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = Context::new();
+
+    {
+        let sums = sums.clone();
+        ctx.register("sum", move |list: Vec<i64>| sums.borrow_mut().push(list.iter().sum::<i64>()));
+    }
+
+    ctx.compile_str(
+        "test",
+        "sum([1, 2, 3])
+        first = (list) => { return list[0] }
+        a = first([7, 8])
+        apply = (f, x) => { return f(x) }
+        b = apply((x) => { return x * 2 }, 21)
+        f = () => { sum([a, b]) }
+        f()
+        ",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*sums.borrow(), vec![6, 49]);
+    assert_eq!(get(&ctx, "a"), Value::Int(7));
+    assert_eq!(get(&ctx, "b"), Value::Int(42));
+}
+
+#[test]
+fn lambdas_in_collections() {
+    let ctx = run(
+        "handlers = [(e) => { return e + 1 }, (e) => { return e * 10 }]
+        obj = { on: (e) => { return [e, { twice: e * 2 }] } }
+        a = handlers[1](5)
+        r = obj.on(4)
+        b = r[1].twice
+        ",
+    );
+
+    assert_eq!(get(&ctx, "a"), Value::Int(50));
+    assert_eq!(get(&ctx, "b"), Value::Int(8));
+}
+
+#[test]
+fn returned_lambdas() {
+    let ctx = run("add = (n) => { return (x) => { return x + n } }\nx = add(1)(2)\n");
+
+    assert_eq!(get(&ctx, "x"), Value::Int(3));
+}
+
+#[test]
+fn in_expressions() {
+    let ctx = run(
+        "same = [1, { a: 2 }] == [1, { a: 2 }]
+        n = 0
+        loop {
+            n = n + 1
+            if n > 2 {
+                break
+            }
+        }
+        loop [] == [] {
+            break
+        }
+        ",
+    );
+
+    assert_eq!(get(&ctx, "same"), Value::Bool(true));
+    assert_eq!(get(&ctx, "n"), Value::Int(3));
+}
+
+#[test]
+fn in_the_ast() {
+    let ast = ast::read_str("test", "x = [1, { b: (y) => { } }]\n").unwrap();
+
+    let exp = match *ast {
+        Ast::Block(ref stmnts) => match *stmnts[0] {
+            Ast::Assign(_, ref exp) => exp.clone(),
+            ref other => panic!("expected assign, got {:?}", other),
+        },
+        ref other => panic!("expected block, got {:?}", other),
+    };
+
+    match *exp {
+        Ast::Array(ref items) => {
+            assert_eq!(items.len(), 2);
+            match *items[1] {
+                Ast::Dict(ref entries) => {
+                    assert_eq!(entries[0].0, "b");
+                    match *entries[0].1 {
+                        Ast::Lambda(ref params, _) => assert_eq!(*params, vec![String::from("y")]),
+                        ref other => panic!("expected lambda, got {:?}", other),
+                    }
+                }
+                ref other => panic!("expected dict, got {:?}", other),
+            }
+        }
+        ref other => panic!("expected array, got {:?}", other),
+    }
+}
@@ -0,0 +1,293 @@
+extern crate runjit;
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use common::{int, run_in};
+
+/// Counts the allocations of the current thread which are still alive,
+/// and separately those with the layout of an `Rc<Value>`, the live values.
+struct Counting;
+
+thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+    static VALUES: Cell<isize> = const { Cell::new(0) };
+    static KEPT: RefCell<Vec<Rc<Value>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The allocation of an `Rc<Value>`, the value after the strong and weak counts.
+fn is_value(layout: Layout) -> bool {
+    let counts = Layout::new::<[usize; 2]>();
+    counts.extend(Layout::new::<Value>()).map(|(l, _)| l.pad_to_align()) == Ok(layout)
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = LIVE.try_with(|l| l.set(l.get() + 1));
+        if is_value(layout) {
+            let _ = VALUES.try_with(|v| v.set(v.get() + 1));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE.try_with(|l| l.set(l.get() - 1));
+        if is_value(layout) {
+            let _ = VALUES.try_with(|v| v.set(v.get() - 1));
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn live() -> isize {
+    LIVE.with(|l| l.get())
+}
+
+fn values() -> isize {
+    VALUES.with(|v| v.get())
+}
+
+/// Keeps a reference to its argument, so it can be checked after the run.
+unsafe extern "C" fn keep(val: *const Value) -> *const Value {
+    let rc = Rc::from_raw(val);
+    KEPT.with(|k| k.borrow_mut().push(rc.clone()));
+    std::mem::forget(rc);
+
+    std::ptr::null()
+}
+
+fn kept() -> Vec<Rc<Value>> {
+    KEPT.with(|k| k.borrow_mut().drain(..).collect())
+}
+
+fn run(source: &str, check: &dyn Fn(&Context)) {
+    let mut ctx = Context::new();
+    ctx.add_fn("keep", keep as *mut _, 1);
+
+    check(&run_in(ctx, source));
+}
+
+/// Runs the script twice, once to warm up lazily allocated state,
+/// and once more to make sure nothing of it outlives the context.
+///
+/// No value outlives the context, except the shared `Null` created by the first run.
+fn assert_no_leak(source: &str, check: &dyn Fn(&Context)) {
+    let start = values();
+    run(source, check);
+    assert!(values() - start <= 1, "{} values outlive the context", values() - start);
+
+    let before = live();
+    let values_before = values();
+    run(source, check);
+
+    assert_eq!(values(), values_before, "{} values outlive the context", values() - values_before);
+    assert_eq!(before, live(), "{} allocations outlive the context", live() - before);
+}
+
+#[test]
+fn values_are_counted() {
+    let before = values();
+    let val = Rc::new(Value::string("counted"));
+    let copy = val.clone();
+
+    assert_eq!(values(), before + 1);
+    drop(val);
+    assert_eq!(values(), before + 1);
+    drop(copy);
+    assert_eq!(values(), before);
+}
+
+#[test]
+fn globals() {
+    assert_no_leak(
+        "a = 1\nb = 1 + 2 * 2\na = \"x\"\na = 3 * 2\nc = null\n",
+        &|ctx| {
+            assert_eq!(int(ctx, "a"), 6);
+            assert_eq!(int(ctx, "b"), 5);
+            assert_eq!(*ctx.get("c").unwrap(), Value::Null);
+        },
+    );
+}
+
+#[test]
+fn loops_and_conditions() {
+    let source = "
+        main = () => {
+            total = 0
+            i = 0
+            loop i < 10 {
+                i = i + 1
+                if i % 2 == 0 {
+                    continue
+                } else if i > 8 {
+                    break
+                }
+                total = total + i
+            }
+            keep(total)
+            keep(i)
+        }
+        main()
+    ";
+
+    assert_no_leak(source, &|_| {
+        let vals: Vec<Value> = kept().iter().map(|v| match **v {
+            Value::Int(i) => Value::Int(i),
+            ref other => panic!("unexpected {:?}", other),
+        }).collect();
+
+        assert_eq!(vals, vec![Value::Int(16), Value::Int(9)]);
+    });
+}
+
+#[test]
+fn collections() {
+    let source = "
+        arr = [1, \"two\", 3]
+        d = {a: 1, b: \"x\", c: 2 + 2}
+        arr = [4]
+        keep(d.a)
+        keep(arr[0])
+    ";
+
+    assert_no_leak(source, &|ctx| {
+        match *ctx.get("arr").unwrap() {
+            Value::Array(ref a) => assert_eq!(*a.borrow()[0], Value::Int(4)),
+            ref other => panic!("expected array, got {:?}", other),
+        }
+
+        let kept: Vec<Value> = kept().iter().map(|v| (**v).clone()).collect();
+        assert_eq!(kept, vec![Value::Int(1), Value::Int(4)]);
+    });
+}
+
+#[test]
+fn lambdas() {
+    let source = "
+        f = (a, b) => {
+            s = a + b
+            n = 0
+            loop {
+                n = n + 1
+                if n > 3 {
+                    break
+                }
+            }
+            if s > 2 {
+                keep(s)
+                return s
+            }
+            s = \"small\"
+            keep(s)
+            keep(a)
+        }
+        g = (x) => {
+            h = (y) => {
+                keep(y)
+            }
+            pair = [x, x]
+            keep(pair)
+            y = x
+            y = \"re\"
+            return
+        }
+        f(1, 2)
+        f(1, 0)
+        g(5)
+        g(2 + 5)
+    ";
+
+    assert_no_leak(source, &|_| {
+        let vals: Vec<Value> = kept()
+            .iter()
+            .map(|v| match **v {
+                Value::Int(i) => Value::Int(i),
+                Value::Str(ref s) => Value::Str(s.clone()),
+                Value::Array(ref a) => Value::Int(a.borrow().len() as i64 * 100),
+                ref other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(
+            vals,
+            vec![
+                Value::Int(3),
+                Value::string("small"),
+                Value::Int(1),
+                Value::Int(200),
+                Value::Int(200),
+            ]
+        );
+    });
+}
+
+#[test]
+fn nested_literals() {
+    let source = "
+        d = {a: [1, {b: \"x\"}], f: (x) => { return [x, {y: x}] }}
+        r = d.f(2)
+        keep(r[1].y)
+        keep(d.a[1].b)
+        d = null
+        r = null
+    ";
+
+    assert_no_leak(source, &|_| {
+        let kept: Vec<Value> = kept().iter().map(|v| (**v).clone()).collect();
+        assert_eq!(kept, vec![Value::Int(2), Value::string("x")]);
+    });
+}
+
+#[test]
+fn closures() {
+    let source = "
+        make = (n, out) => {
+            s = [n]
+            out.add = (x) => {
+                keep(x + n)
+                keep(s)
+            }
+            out.add(1)
+        }
+        fns = {}
+        make(2, fns)
+        fns.add(3)
+        fns = null
+    ";
+
+    assert_no_leak(source, &|_| {
+        let vals: Vec<Value> = kept()
+            .iter()
+            .map(|v| match **v {
+                Value::Int(i) => Value::Int(i),
+                Value::Array(ref a) => Value::Int(a.borrow().len() as i64 * 100),
+                ref other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(vals, vec![Value::Int(3), Value::Int(100), Value::Int(5), Value::Int(100)]);
+    });
+}
+
+#[test]
+fn compile_error() {
+    assert_no_leak("x = 1\n", &|_| {});
+
+    let before = live();
+
+    {
+        let mut ctx = Context::new();
+        assert!(ctx.compile_str("test", "f = () => { x = 1\nbreak }\n").is_err());
+        ctx.compile_str("test", "x = [1, 2]\n").unwrap();
+        ctx.run().unwrap();
+    }
+
+    assert_eq!(live(), before);
+}
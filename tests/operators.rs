@@ -0,0 +1,194 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use runjit::ast;
+use runjit::executor;
+use runjit::jit::{Context, Value};
+use runjit::types;
+use common::eval;
+
+fn is_true(source: &str) -> bool {
+    match eval(source) {
+        Value::Bool(b) => b,
+        other => panic!("not a logical result for {}: {:?}", source, other),
+    }
+}
+
+#[test]
+fn modulo() {
+    assert_eq!(eval("7 % 3"), Value::Int(1));
+    assert_eq!(eval("7.5 % 2"), Value::Float(1.5));
+}
+
+#[test]
+fn equality() {
+    assert!(is_true("1 == 1"));
+    assert!(is_true("\"a\" == \"a\""));
+    assert!(is_true("1 != 2"));
+    assert!(is_true("\"a\" != \"b\""));
+    assert!(!is_true("1 == \"1\""));
+    assert!(is_true("1 != \"1\""));
+}
+
+#[test]
+fn ordering() {
+    assert!(is_true("1 < 2"));
+    assert!(!is_true("2 < 1"));
+    assert!(is_true("2 > 1"));
+    assert!(is_true("2 >= 2"));
+    assert!(is_true("2 <= 2"));
+    assert!(!is_true("3 <= 2"));
+    assert!(is_true("\"abc\" < \"abd\""));
+    assert!(is_true("\"b\" > \"abc\""));
+    assert!(!is_true("1 < \"a\""));
+    assert!(!is_true("1 > \"a\""));
+}
+
+#[test]
+fn logic() {
+    assert!(is_true("1 && 1"));
+    assert!(!is_true("1 && 0"));
+    assert!(is_true("0 || \"\""));
+    assert!(!is_true("0 || 0"));
+    assert!(is_true("1 < 2 && 2 < 3"));
+    assert!(!is_true("1 < 2 && 3 < 2"));
+    assert!(is_true("2 < 1 || 2 < 3"));
+    assert!(is_true("true && \"x\""));
+    assert!(!is_true("false || 0"));
+}
+
+#[test]
+fn booleans() {
+    assert_eq!(eval("true"), Value::Bool(true));
+    assert_eq!(eval("false"), Value::Bool(false));
+    assert!(is_true("true == true"));
+    assert!(is_true("true != false"));
+    assert!(!is_true("true == 1"));
+    assert!(!is_true("false == 0"));
+}
+
+#[test]
+fn truthiness() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str(
+        "test",
+        "a = 0\nb = 0\nc = 0\nn = 0\n\
+         if true { a = 1 }\n\
+         if false { b = 1 } else { b = 2 }\n\
+         if 0 || \"\" { c = 1 }\n\
+         loop n < 3 && true { n = n + 1 }\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    for &(name, val) in &[("a", 1), ("b", 2), ("c", 1), ("n", 3)] {
+        assert_eq!(*ctx.get(name).unwrap(), Value::Int(val), "{}", name);
+    }
+}
+
+#[test]
+fn null() {
+    assert_eq!(eval("null"), Value::Null);
+    assert!(is_true("null == null"));
+    assert!(!is_true("0 == null"));
+    assert!(!is_true("false == null"));
+    assert!(is_true("\"\" != null"));
+    assert!(!is_true("null < 1"));
+    assert!(is_true("null <= null"));
+    assert!(!is_true("null || null"));
+}
+
+#[test]
+fn arrays() {
+    assert!(is_true("[1, 2] == [1, 2]"));
+    assert!(is_true("[] == []"));
+    assert!(is_true("[1] == [1.0]"));
+    assert!(is_true("[[1], \"a\"] == [[1], \"a\"]"));
+    assert!(is_true("[1, 2] != [2, 1]"));
+    assert!(is_true("[1] != [1, 1]"));
+    assert!(is_true("[1, 2] < [1, 3]"));
+    assert!(is_true("[1] < [1, 0]"));
+    assert!(is_true("[1, \"a\"] < [1, \"b\"]"));
+    assert!(is_true("[2] >= [1, 5]"));
+    assert!(is_true("[1] <= [1.0]"));
+    assert!(!is_true("[1] < [\"a\"]"));
+    assert!(!is_true("[1] == 1"));
+    assert!(is_true("[] && 1"));
+}
+
+#[test]
+fn dicts() {
+    assert!(is_true("{a: 1} == {a: 1}"));
+    assert!(is_true("{a: 1, b: [2]} == {b: [2.0], a: 1}"));
+    assert!(is_true("{} == {}"));
+    assert!(is_true("{a: 1} != {a: 2}"));
+    assert!(is_true("{a: 1} != {b: 1}"));
+    assert!(is_true("{a: 1} != {a: 1, b: 1}"));
+    assert!(!is_true("{a: 1} == [1]"));
+    // dicts have no order, only equal ones are `<=` and `>=`
+    assert!(!is_true("{a: 1} < {a: 2}"));
+    assert!(!is_true("{a: 1} > {a: 2}"));
+    assert!(is_true("{a: 1} <= {a: 1}"));
+    assert!(is_true("{x: 1} == {x: 1.0}"));
+    assert!(is_true("{x: 1} >= {x: 1.0}"));
+    assert!(is_true("[{x: 1}] <= [{x: 1.0}]"));
+    assert!(is_true("{} || 0"));
+}
+
+#[test]
+fn null_with_collections() {
+    assert!(!is_true("null == []"));
+    assert!(!is_true("{} == null"));
+    assert!(is_true("null != []"));
+    assert!(!is_true("null < []"));
+    assert!(!is_true("[] >= null"));
+    assert!(is_true("null || []"));
+    assert!(!is_true("null && {}"));
+}
+
+#[test]
+fn short_circuit() {
+    let calls = Rc::new(Cell::new(0));
+    let mut ctx = Context::new();
+
+    {
+        let calls = calls.clone();
+        ctx.register("count", move || {
+            calls.set(calls.get() + 1);
+            true
+        });
+    }
+
+    ctx.compile_str(
+        "test",
+        "x = false && count()\ny = true || count()\nz = null\nok = z != null && z.y == 1\nf = (a) => { return a && count() || a }\ng = f(0)\nh = f(1)\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(calls.get(), 1);
+    assert_eq!(*ctx.get("x").unwrap(), Value::Bool(false));
+    assert_eq!(*ctx.get("y").unwrap(), Value::Bool(true));
+    assert_eq!(*ctx.get("ok").unwrap(), Value::Bool(false));
+    assert_eq!(*ctx.get("g").unwrap(), Value::Bool(false));
+    assert_eq!(*ctx.get("h").unwrap(), Value::Bool(true));
+}
+
+#[test]
+fn executor_short_circuit() {
+    let run = |source: &str| {
+        let ctx = executor::Context::new();
+        ctx.set("f", Rc::new(types::Value::Lambda(vec![String::from("a")], vec![])));
+
+        executor::run(ctx, ast::read_str("test", source).unwrap())
+    };
+
+    assert!(run("if false && f() { }\nif true || f() { }\n").is_ok());
+    match run("if true && f() { }\n") {
+        Err(ref msg) => assert_eq!(msg, "lambda takes 1 arguments, 0 given"),
+        Ok(_) => panic!("expected an error"),
+    }
+}
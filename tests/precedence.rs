@@ -0,0 +1,85 @@
+extern crate runjit;
+
+mod common;
+
+use runjit::jit::Value;
+use common::{eval, tree};
+
+/// Operators by precedence level, weakest first.
+const LEVELS: &[&[(&str, &str)]] = &[
+    &[("||", "Or")],
+    &[("&&", "And")],
+    &[("==", "Eq"), ("!=", "Neq"), (">", "Gt"), ("<", "Le"), (">=", "Gte"), ("<=", "Lee")],
+    &[("+", "Add"), ("-", "Sub")],
+    &[("*", "Mul"), ("/", "Div"), ("%", "Mod")],
+];
+
+#[test]
+fn stronger_operator_binds_first() {
+    for (i, weak_level) in LEVELS.iter().enumerate() {
+        for strong_level in &LEVELS[i + 1..] {
+            for &(weak, weak_name) in weak_level.iter() {
+                for &(strong, strong_name) in strong_level.iter() {
+                    assert_eq!(
+                        tree(&format!("1 {} 2 {} 3", weak, strong)),
+                        format!("({} 1 ({} 2 3))", weak_name, strong_name)
+                    );
+                    assert_eq!(
+                        tree(&format!("1 {} 2 {} 3", strong, weak)),
+                        format!("({} ({} 1 2) 3)", weak_name, strong_name)
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn same_level_is_left_associative() {
+    for level in LEVELS {
+        for &(first, first_name) in level.iter() {
+            for &(second, second_name) in level.iter() {
+                assert_eq!(
+                    tree(&format!("1 {} 2 {} 3", first, second)),
+                    format!("({} ({} 1 2) 3)", second_name, first_name)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn parentheses_group_first() {
+    assert_eq!(tree("(1 + 2) * 3"), "(Mul (Add 1 2) 3)");
+    assert_eq!(tree("1 - (2 - 3)"), "(Sub 1 (Sub 2 3))");
+    assert_eq!(tree("((1))"), "1");
+}
+
+#[test]
+fn mixed_chain() {
+    assert_eq!(
+        tree("1 || 2 && 3 == 4 + 5 * 6"),
+        "(Or 1 (And 2 (Eq 3 (Add 4 (Mul 5 6)))))"
+    );
+    assert_eq!(
+        tree("1 * 2 + 3 == 4 && 5 || 6"),
+        "(Or (And (Eq (Add (Mul 1 2) 3) 4) 5) 6)"
+    );
+    assert_eq!(tree("1 + 2 * 3 - 4 / 5"), "(Sub (Add 1 (Mul 2 3)) (Div 4 5))");
+}
+
+#[test]
+fn jit_evaluates_by_precedence() {
+    assert_eq!(eval("2 * 3 + 4"), Value::Int(10));
+    assert_eq!(eval("2 + 3 * 4"), Value::Int(14));
+    assert_eq!(eval("2 + 3 * 4 - 6 / 2"), Value::Int(11));
+    assert_eq!(eval("2 * (3 + 4)"), Value::Int(14));
+}
+
+#[test]
+fn jit_evaluates_left_to_right() {
+    assert_eq!(eval("10 - 2 - 3"), Value::Int(5));
+    assert_eq!(eval("16 / 4 / 2"), Value::Int(2));
+    assert_eq!(eval("10 - 2 + 3"), Value::Int(11));
+    assert_eq!(eval("12 / 3 * 2"), Value::Int(8));
+}
@@ -0,0 +1,82 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use runjit::Error;
+use common::run;
+
+#[test]
+fn globals_are_read_at_call_time() {
+    let ctx = run("f = () => { y = later }\nlater = 2\nf()\n");
+
+    // `y` is local to the lambda
+    assert_eq!(ctx.get("y"), None);
+    assert_eq!(*ctx.get("later").unwrap(), Value::Int(2));
+
+    let ctx = run("f = () => { return later }\nlater = 2\nf()\n");
+    assert_eq!(*ctx.get("later").unwrap(), Value::Int(2));
+}
+
+#[test]
+fn locals_shadow_globals() {
+    let ctx = run("x = 1\nf = (x) => { x = x + 1\nresult = x }\nf(10)\ng = () => { x = 5 }\ng()\n");
+
+    assert_eq!(*ctx.get("x").unwrap(), Value::Int(1));
+    assert_eq!(ctx.get("result"), None);
+}
+
+#[test]
+fn local_paths() {
+    let ctx = run("p = {x: 1}\nf = (d) => { d.x = d.x + 1\nd[\"y\"] = d.x }\nf(p)\n");
+
+    assert_eq!(
+        *ctx.get("p").unwrap(),
+        Value::dict(vec![("x", Value::Int(2)), ("y", Value::Int(2))])
+    );
+}
+
+#[test]
+fn local_lambdas() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = Context::new();
+
+    {
+        let seen = seen.clone();
+        ctx.register("record", move |v: f64| seen.borrow_mut().push(v));
+    }
+
+    ctx.compile_str(
+        "test",
+        "apply = (f, v) => { f(v) }\napply(record, 1)\nh = {}\nh.f = (v) => { record(v + 1) }\ncall = (d) => { d.f(2) }\ncall(h)\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*seen.borrow(), vec![1.0, 3.0]);
+}
+
+#[test]
+fn undefined_names() {
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "f = () => { y = missing }\nf()\nz = 1\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`missing` is not defined"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    assert_eq!(*ctx.get("z").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn enclosing_lambdas() {
+    match Context::new().compile_str("test", "f = (n) => { g = () => { n = 1 } }\n") {
+        Err(Error::Compile(_, ref msg)) => assert_eq!(
+            msg,
+            "can not assign to `n`, it is captured from an enclosing lambda"
+        ),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
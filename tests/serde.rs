@@ -0,0 +1,148 @@
+#![cfg(feature = "serde")]
+
+extern crate runjit;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::BTreeMap;
+
+use runjit::jit::{from_value, to_value, Context, Value};
+use runjit::Error;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Server {
+    host: String,
+    ports: Vec<u16>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Mode {
+    Fast,
+    Slow { delay: f64 },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    name: String,
+    debug: Option<bool>,
+    server: Server,
+    mode: Mode,
+}
+
+#[derive(Serialize)]
+struct Globals {
+    limit: u32,
+    names: Vec<String>,
+}
+
+fn type_error<T>(res: Result<T, Error>) -> String {
+    match res {
+        Err(Error::Type(msg)) => msg,
+        Err(other) => panic!("expected a type error, got {:?}", other),
+        Ok(_) => panic!("expected a type error"),
+    }
+}
+
+#[test]
+fn get_as() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str(
+        "test",
+        "ports = [80, 8080]\n\
+         server = { host: \"localhost\", ports: ports }\n\
+         config = { name: \"runjit\", server: server, mode: \"Fast\" }\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(
+        ctx.get_as::<Config>("config").unwrap(),
+        Config {
+            name: String::from("runjit"),
+            debug: None,
+            server: Server {
+                host: String::from("localhost"),
+                ports: vec![80, 8080],
+            },
+            mode: Mode::Fast,
+        }
+    );
+    assert_eq!(ctx.get_as::<Option<Config>>("missing").unwrap(), None);
+}
+
+#[test]
+fn key_paths() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str(
+        "test",
+        "ports = [80, \"x\"]\n\
+         server = { host: \"h\", ports: ports }\n\
+         a = { name: \"a\", server: server, mode: \"Fast\" }\n\
+         empty = []\n\
+         server = { ports: empty }\n\
+         b = { name: \"b\", server: server, mode: \"Fast\" }\n\
+         slow = { delay: \"1\" }\n\
+         mode = { Slow: slow }\n\
+         c = { name: \"c\", mode: mode }\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(
+        type_error(ctx.get_as::<Config>("a")),
+        "invalid type: string \"x\", expected u16 at `a.server.ports[1]`"
+    );
+    assert_eq!(
+        type_error(ctx.get_as::<Config>("b")),
+        "missing field `host` at `b.server`"
+    );
+    assert_eq!(
+        type_error(ctx.get_as::<Config>("c")),
+        "invalid type: string \"1\", expected f64 at `c.mode.Slow.delay`"
+    );
+    assert_eq!(
+        type_error(ctx.get_as::<Config>("missing")),
+        "invalid type: unit value, expected struct Config at `missing`"
+    );
+}
+
+#[test]
+fn set_from() {
+    let mut ctx = Context::new();
+
+    ctx.set_from(&Globals {
+        limit: 3,
+        names: vec![String::from("a"), String::from("b")],
+    }).unwrap();
+    ctx.compile_str("test", "double = limit * 2\nfirst = names\n").unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("double").unwrap(), Value::Int(6));
+    assert_eq!(ctx.get_as::<Vec<String>>("first").unwrap(), vec!["a", "b"]);
+
+    assert_eq!(type_error(ctx.set_from(&1.0)), "expected dict, got float");
+}
+
+#[test]
+fn round_trip() {
+    let config = Config {
+        name: String::from("runjit"),
+        debug: Some(true),
+        server: Server {
+            host: String::from("localhost"),
+            ports: vec![1, 2],
+        },
+        mode: Mode::Slow { delay: 0.5 },
+    };
+
+    let val = to_value(&config).unwrap();
+    assert_eq!(from_value::<Config>(&val).unwrap(), config);
+
+    // NUL bytes are fine in keys and values
+    let mut map = BTreeMap::new();
+    map.insert(String::from("nul\0key"), vec![String::from("n\0l")]);
+    let val = to_value(&map).unwrap();
+    assert_eq!(val, Value::dict(vec![("nul\0key", Value::array(vec![Value::string("n\0l")]))]));
+    assert_eq!(from_value::<BTreeMap<String, Vec<String>>>(&val).unwrap(), map);
+}
@@ -0,0 +1,78 @@
+extern crate runjit;
+
+use std::rc::Rc;
+
+use runjit::jit::{Context, Value};
+use runjit::Error;
+
+#[test]
+fn between_variables() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str(
+        "test",
+        "a = [1]\nb = a\nb[0] = 2\nd = { x: 1 }\ne = { inner: d }\ne.inner.y = a\na[0] = 3\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("a").unwrap(), Value::array(vec![Value::Int(3)]));
+    assert_eq!(*ctx.get("b").unwrap(), *ctx.get("a").unwrap());
+
+    match *ctx.get("d").unwrap() {
+        Value::Dict(ref d) => {
+            let y = d.borrow()["y"].clone();
+            assert_eq!(*y, Value::array(vec![Value::Int(3)]));
+        }
+        ref other => panic!("expected dict, got {:?}", other),
+    }
+}
+
+#[test]
+fn push() {
+    let mut ctx = Context::new();
+
+    ctx.compile_str(
+        "test",
+        "a = [1]\nb = a\nb.push(2)\nf = (x) => { x.push(3) }\nf(a)\nappend = b.push\nappend(4)\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    let all = Value::array(vec![Value::Int(1), Value::Int(2), Value::Int(3), Value::Int(4)]);
+    assert_eq!(*ctx.get("a").unwrap(), all);
+    assert_eq!(*ctx.get("b").unwrap(), all);
+
+    let push = ctx.get("append").unwrap();
+    ctx.call(&*push, &[Value::Int(5)]).unwrap();
+
+    match *ctx.get("a").unwrap() {
+        Value::Array(ref a) => assert_eq!(a.borrow().len(), 5),
+        ref other => panic!("expected array, got {:?}", other),
+    }
+
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "a = [1]\na.push(1, 2)\n").unwrap();
+
+    match ctx.run() {
+        Err(Error::Runtime(ref msg)) => assert_eq!(msg, "`a.push` takes 1 arguments, 2 given"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn with_rust() {
+    let list = Value::array(vec![Value::Float(1.0)]);
+    let mut ctx = Context::new();
+
+    ctx.set("list", list.clone());
+    ctx.compile_str("test", "list[0] = 10\nfirst = list[0]\n").unwrap();
+
+    if let Value::Array(ref a) = list {
+        a.borrow_mut().push(Rc::new(Value::Float(2.0)));
+    }
+
+    ctx.run().unwrap();
+
+    assert_eq!(list, Value::array(vec![Value::Int(10), Value::Float(2.0)]));
+    assert_eq!(*ctx.get("list").unwrap(), list);
+    assert_eq!(*ctx.get("first").unwrap(), Value::Int(10));
+}
@@ -0,0 +1,89 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::ast;
+use runjit::jit::{Context, Value};
+use runjit::parser::unescape;
+use runjit::Error;
+use common::eval;
+
+fn compile_error(source: &str) -> (usize, usize, String) {
+    match Context::new().compile_str("test", source) {
+        Err(Error::Compile(loc, msg)) => (loc.line, loc.column, msg),
+        other => panic!("expected a compile error for {}, got {:?}", source, other),
+    }
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(eval(r#""a\tb\nc""#), Value::string("a\tb\nc"));
+    assert_eq!(eval(r#""\"quoted\" \\ \a\b\f\r\v""#), Value::string("\"quoted\" \\ \x07\x08\x0c\r\x0b"));
+    assert_eq!(eval(r#""caf\u00e9 \u20AC""#), Value::string("café €"));
+    assert_eq!(eval(r#""""#), Value::string(""));
+    assert_eq!(eval(r#""héllo""#), Value::string("héllo"));
+}
+
+#[test]
+fn nul_bytes() {
+    let lens = Rc::new(RefCell::new(Vec::new()));
+    let mut ctx = Context::new();
+
+    {
+        let lens = lens.clone();
+        ctx.register("len", move |s: &str| lens.borrow_mut().push(s.len()));
+    }
+
+    ctx.compile_str(
+        "test",
+        "s = \"a\\u0000b\"\nlen(s)\nd = {}\nd[s] = 1\nsame = s == \"a\\u0000b\"\nother = s == \"a\"\n",
+    ).unwrap();
+    ctx.run().unwrap();
+
+    assert_eq!(*ctx.get("s").unwrap(), Value::string("a\0b"));
+    assert_eq!(*lens.borrow(), vec![3]);
+    assert_eq!(*ctx.get("d").unwrap(), Value::dict(vec![("a\0b", Value::Int(1))]));
+    assert_eq!(*ctx.get("same").unwrap(), Value::Bool(true));
+    assert_eq!(*ctx.get("other").unwrap(), Value::Bool(false));
+
+    ctx.set("nul\0name", "value");
+    assert_eq!(*ctx.get("nul\0name").unwrap(), Value::string("value"));
+}
+
+#[test]
+fn invalid_escapes() {
+    assert_eq!(
+        compile_error("x = 1\ny = \"a\\qb\"\n"),
+        (2, 5, String::from("invalid escape `\\q`"))
+    );
+    assert_eq!(
+        compile_error("x = \"\\u12\"\n"),
+        (1, 5, String::from("invalid unicode escape `\\u12`"))
+    );
+    assert_eq!(
+        compile_error("x = \"\\ud800\"\n"),
+        (1, 5, String::from("invalid unicode escape `\\ud800`"))
+    );
+
+    assert_eq!(unescape(r"\u00e9\\"), Ok(String::from("é\\")));
+    assert_eq!(unescape("a\\"), Err(String::from("incomplete escape at the end of the string")));
+}
+
+#[test]
+fn invalid_escapes_in_the_ast() {
+    match ast::read_str("test", "x = 1\ny = \"a\\qb\"\n") {
+        Err(Error::Compile(ref loc, ref msg)) => {
+            assert_eq!((loc.line, loc.column), (2, 5));
+            assert_eq!(msg, "invalid escape `\\q`");
+        }
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+
+    match ast::read_str("test", "x = 99999999999999999999\n") {
+        Err(Error::Compile(_, ref msg)) => assert_eq!(msg, "integer literal 99999999999999999999 is too large"),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
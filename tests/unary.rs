@@ -0,0 +1,112 @@
+extern crate runjit;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use runjit::ast;
+use runjit::executor;
+use runjit::jit::{Context, Value};
+use runjit::types::{self, Args, RustCall};
+use runjit::Error;
+use common::{eval_in, runtime_error, tree};
+
+fn eval(source: &str) -> Value {
+    let mut ctx = Context::new();
+
+    ctx.set("i", 5);
+    ctx.set("f", 2.5);
+    ctx.set("s", "text");
+
+    eval_in(ctx, source)
+}
+
+#[test]
+fn literals() {
+    assert_eq!(eval("-5"), Value::Int(-5));
+    assert_eq!(eval("+5"), Value::Int(5));
+    assert_eq!(eval("-2.5"), Value::Float(-2.5));
+    assert_eq!(eval("--5"), Value::Int(5));
+    assert_eq!(eval("-(2 + 3)"), Value::Int(-5));
+    assert_eq!(eval("-(-(1))"), Value::Int(1));
+    assert_eq!(eval("!true"), Value::Bool(false));
+    assert_eq!(eval("!0"), Value::Bool(true));
+    assert_eq!(eval("!null"), Value::Bool(true));
+    assert_eq!(eval("!!2.5"), Value::Bool(true));
+    assert_eq!(eval("-9223372036854775808"), Value::Int(i64::MIN));
+
+    match Context::new().compile_str("test", "x = -9223372036854775809\n") {
+        Err(Error::Compile(_, ref msg)) => assert_eq!(msg, "integer literal -9223372036854775809 is too large"),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
+
+#[test]
+fn variables() {
+    assert_eq!(eval("-i"), Value::Int(-5));
+    assert_eq!(eval("+i"), Value::Int(5));
+    assert_eq!(eval("-f"), Value::Float(-2.5));
+    assert_eq!(eval("!i"), Value::Bool(false));
+    assert_eq!(eval("!s"), Value::Bool(false));
+    assert_eq!(eval("1 - -i"), Value::Int(6));
+    assert_eq!(eval("-i * 2"), Value::Int(-10));
+    assert_eq!(eval("!i == false"), Value::Bool(true));
+    assert_eq!(eval("!(i == 5) || i > 4"), Value::Bool(true));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(runtime_error("s = \"a\"\nx = -s\n"), "can not apply unary `-` to string");
+    assert_eq!(runtime_error("x = +null\n"), "can not apply unary `+` to null");
+    assert_eq!(
+        runtime_error("m = -9223372036854775807 - 1\nx = -m\n"),
+        "integer overflow in `-(-9223372036854775808)`"
+    );
+
+    // the script goes on with `Null`
+    let mut ctx = Context::new();
+    ctx.compile_str("test", "x = -[1]\ny = 1\n").unwrap();
+    assert!(ctx.run().is_err());
+    assert_eq!(*ctx.get("x").unwrap(), Value::Null);
+    assert_eq!(*ctx.get("y").unwrap(), Value::Int(1));
+}
+
+#[test]
+fn precedence() {
+    assert_eq!(tree("-a * b"), "(Mul (Neg var) var)");
+    assert_eq!(tree("!a == b"), "(Eq (Not var) var)");
+    assert_eq!(tree("a - -b"), "(Sub var (Neg var))");
+    assert_eq!(tree("-(a + b)"), "(Neg (Add var var))");
+    assert_eq!(tree("+-a"), "(Pos (Neg var))");
+    assert_eq!(tree("-5 + 1"), "(Add -5 1)");
+}
+
+#[test]
+fn executor() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let ctx = executor::Context::new();
+
+    {
+        let seen = seen.clone();
+        ctx.set(
+            "record",
+            RustCall::new(move |args: &Args| {
+                for arg in args {
+                    seen.borrow_mut().push(match **arg {
+                        types::Value::Int(i) => format!("{}", i),
+                        types::Value::Float(f) => format!("{:?}", f),
+                        types::Value::Bool(b) => format!("{}", b),
+                        _ => String::from("other"),
+                    });
+                }
+                Ok(Rc::new(types::Value::Null))
+            }),
+        );
+    }
+
+    let ast = ast::read_str("test", "record(-5, +2, -(2.5), !0, !true, -(1 + 2))\n").unwrap();
+    executor::run(ctx, ast).unwrap();
+
+    assert_eq!(*seen.borrow(), vec!["-5", "2", "-2.5", "true", "false", "-3"]);
+}